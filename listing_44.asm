@@ -29,4 +29,15 @@ mov di, dx
 mov dx, sp
 mov cx, bp
 mov bx, si
-mov ax, di
\ No newline at end of file
+mov ax, di
+
+; Final registers:
+;       ax: 0x0004 (4)
+;       bx: 0x0003 (3)
+;       cx: 0x0002 (2)
+;       dx: 0x0001 (1)
+;       sp: 0x0001 (1)
+;       bp: 0x0002 (2)
+;       si: 0x0003 (3)
+;       di: 0x0004 (4)
+;       ip: 0x001c (28)
@@ -25,4 +25,11 @@ mov bp, 999
 cmp bp, sp
 
 add bp, 1027
-sub bp, 2026
\ No newline at end of file
+sub bp, 2026
+
+; Final registers:
+;       bx: 0xe102 (57602)
+;       cx: 0x0f01 (3841)
+;       sp: 0x03e6 (998)
+;       ip: 0x0018 (24)
+;    flags: PZ
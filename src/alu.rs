@@ -0,0 +1,304 @@
+//! Arithmetic, logic and shift operations along with the FLAGS they produce.
+//!
+//! Values are passed around as `u16` regardless of the operation size; byte operations only look
+//! at (and only produce) the low 8 bits.
+
+use crate::cpu_state::*;
+use crate::instruction::Op;
+
+/// Whether `op` is one of the operations handled by [`compute`]
+pub(crate) fn is_alu_op(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Add
+            | Op::Adc
+            | Op::Sub
+            | Op::Sbb
+            | Op::Cmp
+            | Op::And
+            | Op::Or
+            | Op::Xor
+            | Op::Test
+            | Op::Inc
+            | Op::Dec
+            | Op::Neg
+            | Op::Not
+            | Op::Shl
+            | Op::Shr
+            | Op::Sar
+            | Op::Rol
+            | Op::Ror
+            | Op::Rcl
+            | Op::Rcr
+    )
+}
+
+/// cmp and test only compute flags, every other operation writes its result back
+pub(crate) fn writes_result(op: Op) -> bool {
+    !matches!(op, Op::Cmp | Op::Test)
+}
+
+/// Computes `destination op source` and updates `flags` the way the 8086 does. Single operand
+/// operations ignore `source`, shifts and rotates use it as the count.
+pub(crate) fn compute(
+    op: Op,
+    destination: u16,
+    source: u16,
+    is_wide: bool,
+    flags: &mut u16,
+) -> u16 {
+    let width = Width::new(is_wide);
+    let carry = *flags & CARRY_FLAG != 0;
+
+    match op {
+        Op::Add => add(width, destination, source, false, flags),
+        Op::Adc => add(width, destination, source, carry, flags),
+        Op::Sub | Op::Cmp => sub(width, destination, source, false, flags),
+        Op::Sbb => sub(width, destination, source, carry, flags),
+
+        // inc/dec are the only arithmetic operations that leave CF alone
+        Op::Inc => {
+            let result = add(width, destination, 1, false, flags);
+            set(flags, CARRY_FLAG, carry);
+            result
+        }
+        Op::Dec => {
+            let result = sub(width, destination, 1, false, flags);
+            set(flags, CARRY_FLAG, carry);
+            result
+        }
+        Op::Neg => sub(width, 0, destination, false, flags),
+
+        Op::And | Op::Test => logic(width, destination & source, flags),
+        Op::Or => logic(width, destination | source, flags),
+        Op::Xor => logic(width, destination ^ source, flags),
+        Op::Not => !destination & width.mask,
+
+        _ => shift(op, width, destination, source, flags),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Width {
+    mask: u16,
+    sign_bit: u16,
+}
+
+impl Width {
+    fn new(is_wide: bool) -> Self {
+        match is_wide {
+            true => Width {
+                mask: 0xFFFF,
+                sign_bit: 0x8000,
+            },
+            false => Width {
+                mask: 0xFF,
+                sign_bit: 0x80,
+            },
+        }
+    }
+}
+
+fn add(width: Width, a: u16, b: u16, carry_in: bool, flags: &mut u16) -> u16 {
+    let (a, b) = (a & width.mask, b & width.mask);
+    let full = a as u32 + b as u32 + carry_in as u32;
+    let result = full as u16 & width.mask;
+
+    set(flags, CARRY_FLAG, full > width.mask as u32);
+    set(flags, AUXILIARY_CARRY_FLAG, (a ^ b ^ result) & 0x10 != 0);
+    // Overflow when both operands have the same sign and the result doesn't
+    set(
+        flags,
+        OVERFLOW_FLAG,
+        (a ^ result) & (b ^ result) & width.sign_bit != 0,
+    );
+    set_result_flags(width, result, flags);
+
+    result
+}
+
+fn sub(width: Width, a: u16, b: u16, borrow_in: bool, flags: &mut u16) -> u16 {
+    let (a, b) = (a & width.mask, b & width.mask);
+    let result = a.wrapping_sub(b).wrapping_sub(borrow_in as u16) & width.mask;
+
+    set(flags, CARRY_FLAG, b as u32 + borrow_in as u32 > a as u32);
+    set(flags, AUXILIARY_CARRY_FLAG, (a ^ b ^ result) & 0x10 != 0);
+    // Overflow when the operands have different signs and the result took the sign of b
+    set(
+        flags,
+        OVERFLOW_FLAG,
+        (a ^ b) & (a ^ result) & width.sign_bit != 0,
+    );
+    set_result_flags(width, result, flags);
+
+    result
+}
+
+fn logic(width: Width, result: u16, flags: &mut u16) -> u16 {
+    let result = result & width.mask;
+
+    set(flags, CARRY_FLAG, false);
+    set(flags, OVERFLOW_FLAG, false);
+    set(flags, AUXILIARY_CARRY_FLAG, false);
+    set_result_flags(width, result, flags);
+
+    result
+}
+
+/// Shifts and rotates one bit at a time like the 8086 microcode does. The count is not masked,
+/// so a count of 0 leaves everything, flags included, untouched
+fn shift(op: Op, width: Width, value: u16, count: u16, flags: &mut u16) -> u16 {
+    let count = count & 0xFF;
+    let mut value = value & width.mask;
+    let mut carry = *flags & CARRY_FLAG != 0;
+
+    if count == 0 {
+        return value;
+    }
+
+    // OF is only defined for single bit shifts, where it tells whether the sign changed. For
+    // longer shifts we report what the final single bit step would have produced
+    let mut overflow = false;
+
+    for _ in 0..count {
+        let msb = value & width.sign_bit != 0;
+        let lsb = value & 1 != 0;
+
+        value = match op {
+            Op::Shl => {
+                carry = msb;
+                (value << 1) & width.mask
+            }
+            Op::Shr => {
+                carry = lsb;
+                overflow = msb;
+                value >> 1
+            }
+            Op::Sar => {
+                carry = lsb;
+                (value >> 1) | (value & width.sign_bit)
+            }
+            Op::Rol => {
+                carry = msb;
+                ((value << 1) | msb as u16) & width.mask
+            }
+            Op::Ror => {
+                carry = lsb;
+                (value >> 1) | if lsb { width.sign_bit } else { 0 }
+            }
+            Op::Rcl => {
+                let result = ((value << 1) | carry as u16) & width.mask;
+                carry = msb;
+                result
+            }
+            Op::Rcr => {
+                let result = (value >> 1) | if carry { width.sign_bit } else { 0 };
+                carry = lsb;
+                result
+            }
+            _ => value,
+        };
+    }
+
+    let msb = value & width.sign_bit != 0;
+    let next_msb = value & (width.sign_bit >> 1) != 0;
+
+    overflow = match op {
+        Op::Shl | Op::Rol | Op::Rcl => msb != carry,
+        Op::Ror | Op::Rcr => msb != next_msb,
+        Op::Sar => false,
+        _ => overflow,
+    };
+
+    set(flags, CARRY_FLAG, carry);
+    set(flags, OVERFLOW_FLAG, overflow);
+
+    // Rotates only ever touch CF and OF
+    if matches!(op, Op::Shl | Op::Shr | Op::Sar) {
+        set_result_flags(width, value, flags);
+    }
+
+    value
+}
+
+/// The decimal adjustments aaa/aas/daa/das/aam/aad, returning the new AX. `base` is the number
+/// base of aam and aad, which is 10 unless the immediate says otherwise. aam by 0 is a divide
+/// error and returns `None`. Flags the manual leaves undefined are left alone
+pub(crate) fn adjust(op: Op, ax: u16, base: u8, flags: &mut u16) -> Option<u16> {
+    let byte = Width::new(false);
+    let [mut al, mut ah] = ax.to_le_bytes();
+    let carry = *flags & CARRY_FLAG != 0;
+    let low_digit_overflow = al & 0x0F > 9 || *flags & AUXILIARY_CARRY_FLAG != 0;
+
+    match op {
+        // Unpacked BCD, one digit in AL that carries into or borrows from AH
+        Op::Aaa | Op::Aas => {
+            if low_digit_overflow {
+                (al, ah) = match op {
+                    Op::Aaa => (al.wrapping_add(6), ah.wrapping_add(1)),
+                    _ => (al.wrapping_sub(6), ah.wrapping_sub(1)),
+                };
+            }
+
+            al &= 0x0F;
+            set(flags, AUXILIARY_CARRY_FLAG, low_digit_overflow);
+            set(flags, CARRY_FLAG, low_digit_overflow);
+        }
+
+        // Packed BCD, two digits in AL
+        Op::Daa | Op::Das => {
+            let high_digit_overflow = al > 0x99 || carry;
+            // das also borrows when taking 6 off a low digit that is smaller than that
+            let borrow = op == Op::Das && low_digit_overflow && al < 6;
+
+            if low_digit_overflow {
+                al = match op {
+                    Op::Daa => al.wrapping_add(6),
+                    _ => al.wrapping_sub(6),
+                };
+            }
+            if high_digit_overflow {
+                al = match op {
+                    Op::Daa => al.wrapping_add(0x60),
+                    _ => al.wrapping_sub(0x60),
+                };
+            }
+
+            set(flags, AUXILIARY_CARRY_FLAG, low_digit_overflow);
+            set(flags, CARRY_FLAG, high_digit_overflow || borrow);
+            set_result_flags(byte, al as u16, flags);
+        }
+
+        Op::Aam => {
+            (ah, al) = (al.checked_div(base)?, al % base);
+            set_result_flags(byte, al as u16, flags);
+        }
+        Op::Aad => {
+            (ah, al) = (0, ah.wrapping_mul(base).wrapping_add(al));
+            set_result_flags(byte, al as u16, flags);
+        }
+
+        _ => {}
+    }
+
+    Some(u16::from_le_bytes([al, ah]))
+}
+
+/// Sets ZF, SF and PF from the result of an operation
+fn set_result_flags(width: Width, result: u16, flags: &mut u16) {
+    set(flags, ZERO_FLAG, result & width.mask == 0);
+    set(flags, SIGN_FLAG, result & width.sign_bit != 0);
+    // PF only ever looks at the low 8 bits, even for word operations
+    set(
+        flags,
+        PARITY_FLAG,
+        (result as u8).count_ones().is_multiple_of(2),
+    );
+}
+
+fn set(flags: &mut u16, mask: u16, value: bool) {
+    match value {
+        true => *flags |= mask,
+        false => *flags &= !mask,
+    }
+}
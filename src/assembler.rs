@@ -0,0 +1,917 @@
+//! A small assembler for the NASM syntax the disassembler prints, so that decoded listings can be
+//! turned back into machine code without needing NASM itself.
+//!
+//! Rather than carrying a second copy of the encoding rules, the assembler works backwards from
+//! the decoder's opcode table: every table entry for the mnemonic is tried, each candidate
+//! encoding is decoded again, and the shortest one that decodes back to the instruction that was
+//! written wins. Jump targets are resolved over several passes until every label settles.
+
+use crate::decoder::decode;
+use crate::instruction::*;
+use crate::opcode_table::{Entry, Layout, OPCODE_TABLE};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Passes over the source before giving up on the jump sizes settling down
+const MAX_PASSES: usize = 16;
+
+/// Why a piece of source couldn't be assembled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    /// 1-based line the error was found on
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// Assembles `source` into a flat binary that starts at offset 0
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let statements = parse(source)?;
+    let mut labels = HashMap::new();
+
+    // Forward jumps are assumed to be short the first time around. Every pass places the labels
+    // using the sizes of the previous one, until nothing moves anymore
+    for _ in 0..MAX_PASSES {
+        let (bytes, placed) = encode_all(&statements, &labels)?;
+
+        if placed == labels {
+            return Ok(bytes);
+        }
+
+        labels = placed;
+    }
+
+    Err(AssembleError {
+        line: 0,
+        message: format!("jump sizes didn't settle after {} passes", MAX_PASSES),
+    })
+}
+
+/// A single line of source
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Statement {
+    line: usize,
+    labels: Vec<String>,
+    body: Body,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Body {
+    Empty,
+    Data(Vec<u8>),
+    Instruction(Template),
+}
+
+/// An instruction as written, before its jump target is known
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template {
+    op: Op,
+    operands: [Option<Argument>; 2],
+    /// Operation size if the source pins it down, through a register or a size keyword
+    is_wide: Option<bool>,
+    is_lock: bool,
+    /// `FLAG_REP` or `FLAG_REPNE` if the instruction is repeated
+    repeat: u8,
+    is_far: bool,
+    segment_override: Option<Reg>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Argument {
+    Operand(Operand),
+    Target(Target),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Label(String),
+    /// A plain number, which NASM treats as an absolute address
+    Absolute(usize),
+    /// `$+n`, relative to the start of the instruction
+    Here(isize),
+}
+
+fn parse(source: &str) -> Result<Vec<Statement>, AssembleError> {
+    let mut statements = Vec::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AssembleError { line, message };
+
+        let mut text = text.split(';').next().unwrap_or("").trim();
+        let mut labels = Vec::new();
+
+        while let Some((label, rest)) = split_label(text) {
+            labels.push(label.to_string());
+            text = rest.trim();
+        }
+
+        let body = parse_body(text).map_err(error)?;
+        statements.push(Statement { line, labels, body });
+    }
+
+    // Catch typos in label names up front, rather than as a jump that never settles
+    let defined: Vec<&String> = statements.iter().flat_map(|s| &s.labels).collect();
+    for statement in &statements {
+        if let Body::Instruction(template) = &statement.body {
+            for argument in template.operands.iter().flatten() {
+                if let Argument::Target(Target::Label(label)) = argument {
+                    if !defined.contains(&label) {
+                        return Err(AssembleError {
+                            line: statement.line,
+                            message: format!("undefined label `{}`", label),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(statements)
+}
+
+/// Splits `label: rest` into its parts
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+
+    match is_identifier(label) && parse_register(label).is_none() {
+        true => Some((label, rest)),
+        false => None,
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_body(text: &str) -> Result<Body, String> {
+    if text.is_empty() {
+        return Ok(Body::Empty);
+    }
+
+    let (mut mnemonic, mut rest) = split_word(text);
+    let mut is_lock = false;
+    let mut repeat = 0;
+    let mut segment_override = None;
+
+    // Prefixes come as words of their own in front of the mnemonic. A segment register is only a
+    // prefix when something follows it, like in `es movsb`
+    loop {
+        match mnemonic.as_str() {
+            "lock" => is_lock = true,
+            "rep" | "repe" | "repz" => repeat = FLAG_REP,
+            "repne" | "repnz" => repeat = FLAG_REPNE,
+            word if !rest.is_empty() && parse_register(word).is_some_and(|r| r.is_segment()) => {
+                segment_override = parse_register(word);
+            }
+            _ => break,
+        }
+
+        (mnemonic, rest) = split_word(rest);
+    }
+
+    match mnemonic.as_str() {
+        "bits" => {
+            return match rest {
+                "16" => Ok(Body::Empty),
+                _ => Err(format!(
+                    "only 16-bit code is supported, not `bits {}`",
+                    rest
+                )),
+            }
+        }
+        "db" => {
+            let bytes = rest
+                .split(',')
+                .map(|value| {
+                    let value = value.trim();
+                    match parse_number(value) {
+                        Some(number) if fits_byte(number) => Ok(number as u8),
+                        Some(_) => Err(format!("`{}` doesn't fit in a byte", value)),
+                        None => Err(format!("invalid byte `{}`", value)),
+                    }
+                })
+                .collect::<Result<_, _>>()?;
+
+            return Ok(Body::Data(bytes));
+        }
+        _ => {}
+    }
+
+    // String instructions carry their size in the mnemonic, as in `movsb` and `movsw`
+    let mut is_wide = None;
+    let op = match lookup_op(&mnemonic) {
+        Some(op) => op,
+        None => {
+            let (stem, wide) = match (mnemonic.strip_suffix('b'), mnemonic.strip_suffix('w')) {
+                (Some(stem), _) => (stem, false),
+                (_, Some(stem)) => (stem, true),
+                _ => return Err(format!("unknown mnemonic `{}`", mnemonic)),
+            };
+            let op = lookup_op(stem)
+                .filter(|op| op.is_string())
+                .ok_or_else(|| format!("unknown mnemonic `{}`", mnemonic))?;
+
+            is_wide = Some(wide);
+            op
+        }
+    };
+
+    // Jump distance keywords come right after the mnemonic
+    let mut is_far = false;
+    let (keyword, after_keyword) = split_word(rest);
+    match keyword.as_str() {
+        "far" => (is_far, rest) = (true, after_keyword),
+        "near" => (is_wide, rest) = (Some(true), after_keyword),
+        "short" => (is_wide, rest) = (Some(false), after_keyword),
+        _ => {}
+    }
+
+    let mut operands = [None, None];
+    let mut texts = Vec::new();
+
+    if !rest.is_empty() {
+        texts = rest.split(',').map(str::trim).collect();
+        if texts.len() > 2 {
+            return Err(format!("too many operands in `{}`", text));
+        }
+
+        for (slot, &text) in operands.iter_mut().zip(&texts) {
+            let (size, text) = match split_word(text) {
+                (word, rest) if word == "byte" => (Some(false), rest),
+                (word, rest) if word == "word" => (Some(true), rest),
+                _ => (None, text),
+            };
+
+            is_wide = size.or(is_wide);
+
+            let argument = parse_operand(op, text, &mut segment_override)?;
+            *slot = Some(argument);
+        }
+    }
+
+    if is_wide.is_none() {
+        is_wide = implied_width(op, &operands);
+    }
+
+    // Byte immediates are stored the way the decoder produces them, without any sign extension.
+    // Port numbers, interrupt vectors and the base of aam and aad are always a byte
+    if is_wide == Some(false) || matches!(op, Op::In | Op::Out | Op::Int | Op::Aam | Op::Aad) {
+        for (argument, text) in operands.iter_mut().zip(&texts) {
+            if let Some(Argument::Operand(Operand::Immediate(value))) = argument {
+                // Words keep their sign as the top bits, so -1 arrives here as 0xFFFF
+                if !fits_byte(*value as i16 as i64) && !fits_byte(*value as i64) {
+                    return Err(format!("`{}` doesn't fit in a byte", text));
+                }
+                *value &= 0xFF;
+            }
+        }
+    }
+
+    Ok(Body::Instruction(Template {
+        op,
+        operands,
+        is_wide,
+        is_lock,
+        repeat,
+        is_far,
+        segment_override,
+    }))
+}
+
+/// Splits off the first whitespace separated word, lowercased
+fn split_word(text: &str) -> (String, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word.to_ascii_lowercase(), rest.trim()),
+        None => (text.to_ascii_lowercase(), ""),
+    }
+}
+
+/// The operation size implied by the register operands, if any
+fn implied_width(op: Op, operands: &[Option<Argument>; 2]) -> Option<bool> {
+    let registers = operands
+        .iter()
+        .enumerate()
+        .filter_map(|(index, argument)| match argument {
+            Some(Argument::Operand(Operand::Register(reg))) => Some((index, *reg)),
+            _ => None,
+        });
+
+    registers
+        .filter(|(index, reg)| match op {
+            // The count of a shift says nothing about the size of the shifted value
+            _ if op.is_shift() => *index == 0,
+            // Neither does the port number in DX
+            Op::In | Op::Out => *reg != Reg::Dx,
+            _ => true,
+        })
+        .map(|(_, reg)| reg.is_wide())
+        .next()
+}
+
+fn parse_operand(
+    op: Op,
+    text: &str,
+    segment_override: &mut Option<Reg>,
+) -> Result<Argument, String> {
+    let invalid = || format!("invalid operand `{}`", text);
+
+    if let Some(reg) = parse_register(text) {
+        return Ok(Argument::Operand(Operand::Register(reg)));
+    }
+
+    // `es:[bx]` is accepted as well as the NASM style `[es:bx]`
+    if let Some((segment, rest)) = text.split_once(':') {
+        if let (Some(segment), true) =
+            (parse_register(segment.trim()), rest.trim().starts_with('['))
+        {
+            *segment_override = Some(segment);
+            return parse_operand(op, rest.trim(), segment_override);
+        }
+    }
+
+    if let Some(inner) = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+    {
+        let address = parse_address(inner, segment_override).ok_or_else(invalid)?;
+        return Ok(Argument::Operand(Operand::Memory(address)));
+    }
+
+    // segment:offset of a direct far jump or call
+    if let Some((segment, offset)) = text.split_once(':') {
+        let segment = parse_number(segment.trim()).filter(|&value| fits_word(value));
+        let offset = parse_number(offset.trim()).filter(|&value| fits_word(value));
+        let (segment, offset) = segment.zip(offset).ok_or_else(invalid)?;
+
+        return Ok(Argument::Operand(Operand::FarPointer {
+            segment: segment as u16,
+            offset: offset as u16,
+        }));
+    }
+
+    if is_branch(op) {
+        if let Some(relative) = text.strip_prefix('$') {
+            let relative = match relative.trim() {
+                "" => 0,
+                relative => parse_number(&relative.replace(' ', "")).ok_or_else(invalid)?,
+            };
+
+            return Ok(Argument::Target(Target::Here(relative as isize)));
+        }
+
+        if let Some(target) = parse_number(text) {
+            return Ok(Argument::Target(Target::Absolute(target as usize)));
+        }
+
+        if is_identifier(text) {
+            return Ok(Argument::Target(Target::Label(text.to_string())));
+        }
+    }
+
+    let value = parse_number(text).ok_or_else(invalid)?;
+    if !fits_word(value) {
+        return Err(format!("`{}` doesn't fit in a word", text));
+    }
+
+    Ok(Argument::Operand(Operand::Immediate(value as u16)))
+}
+
+/// Whether `value` can be stored in a byte, either as a signed or an unsigned number
+fn fits_byte(value: i64) -> bool {
+    (-0x80..=0xFF).contains(&value)
+}
+
+/// Whether `value` can be stored in a word, either as a signed or an unsigned number
+fn fits_word(value: i64) -> bool {
+    (-0x8000..=0xFFFF).contains(&value)
+}
+
+/// Parses the inside of `[...]`, like `es:bx + si - 32`
+fn parse_address(text: &str, segment_override: &mut Option<Reg>) -> Option<EffectiveAddress> {
+    let mut text = text.trim();
+
+    if let Some((segment, rest)) = text.split_once(':') {
+        *segment_override = Some(parse_register(segment.trim()).filter(|reg| reg.is_segment())?);
+        text = rest;
+    }
+
+    let mut registers = Vec::new();
+    let mut displacement: i64 = 0;
+
+    // Split into terms, keeping the sign in front of each one
+    let mut terms = Vec::new();
+    let mut term = String::new();
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        if (c == '+' || c == '-') && !term.is_empty() {
+            terms.push(std::mem::take(&mut term));
+        }
+        term.push(c);
+    }
+    terms.push(term);
+
+    for term in terms {
+        let (negative, value) = match term.strip_prefix('-') {
+            Some(value) => (true, value),
+            None => (false, term.strip_prefix('+').unwrap_or(&term)),
+        };
+
+        match parse_register(value) {
+            Some(reg) if !negative => registers.push(reg),
+            Some(_) => return None,
+            None => {
+                let value = parse_number(value)?;
+                displacement = displacement.checked_add(if negative { -value } else { value })?;
+            }
+        }
+    }
+
+    registers.sort_by_key(|reg| reg.name());
+
+    let base = match registers.as_slice() {
+        [] => AddressBase::Direct,
+        [Reg::Bx, Reg::Si] => AddressBase::BxSi,
+        [Reg::Bx, Reg::Di] => AddressBase::BxDi,
+        [Reg::Bp, Reg::Si] => AddressBase::BpSi,
+        [Reg::Bp, Reg::Di] => AddressBase::BpDi,
+        [Reg::Si] => AddressBase::Si,
+        [Reg::Di] => AddressBase::Di,
+        [Reg::Bp] => AddressBase::Bp,
+        [Reg::Bx] => AddressBase::Bx,
+        _ => return None,
+    };
+
+    if !fits_word(displacement) {
+        return None;
+    }
+
+    Some(EffectiveAddress {
+        base,
+        displacement: displacement as u16 as i16,
+    })
+}
+
+/// Parses decimal, `0x` prefixed or `h` suffixed hexadecimal numbers, with an optional sign
+pub(crate) fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let digits = digits.to_ascii_lowercase();
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(hex) = digits.strip_suffix('h') {
+        // NASM wants hex with a suffix to start with a digit, so it can't be mistaken for a name
+        if !hex.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        i64::from_str_radix(hex, 16).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+pub(crate) fn parse_register(text: &str) -> Option<Reg> {
+    let reg = match text.to_ascii_lowercase().as_str() {
+        "al" => Reg::Al,
+        "cl" => Reg::Cl,
+        "dl" => Reg::Dl,
+        "bl" => Reg::Bl,
+        "ah" => Reg::Ah,
+        "ch" => Reg::Ch,
+        "dh" => Reg::Dh,
+        "bh" => Reg::Bh,
+        "ax" => Reg::Ax,
+        "cx" => Reg::Cx,
+        "dx" => Reg::Dx,
+        "bx" => Reg::Bx,
+        "sp" => Reg::Sp,
+        "bp" => Reg::Bp,
+        "si" => Reg::Si,
+        "di" => Reg::Di,
+        "es" => Reg::Es,
+        "cs" => Reg::Cs,
+        "ss" => Reg::Ss,
+        "ds" => Reg::Ds,
+        _ => return None,
+    };
+
+    Some(reg)
+}
+
+/// Finds the operation for a mnemonic, accepting the usual NASM aliases too
+fn lookup_op(mnemonic: &str) -> Option<Op> {
+    let mnemonic = match mnemonic {
+        "jz" => "je",
+        "jnz" => "jne",
+        "jc" | "jnae" => "jb",
+        "jnc" | "jae" => "jnb",
+        "jna" => "jbe",
+        "jnbe" => "ja",
+        "jpe" => "jp",
+        "jpo" => "jnp",
+        "jnge" => "jl",
+        "jge" => "jnl",
+        "jng" => "jle",
+        "jnle" => "jg",
+        "loope" => "loopz",
+        "loopne" => "loopnz",
+        "sal" => "shl",
+        "xlatb" => "xlat",
+        "retn" => "ret",
+        mnemonic => mnemonic,
+    };
+
+    table_entries()
+        .map(|(_, _, op, _)| op)
+        .find(|op| op.mnemonic() == mnemonic)
+}
+
+/// Whether the operation can take a relative jump target
+fn is_branch(op: Op) -> bool {
+    table_entries().any(|(_, _, entry_op, layout)| {
+        entry_op == op && matches!(layout, Layout::ShortJump | Layout::NearJump)
+    })
+}
+
+/// Every instruction in the opcode table as (opcode, REG field for group entries, op, layout)
+fn table_entries() -> impl Iterator<Item = (u8, Option<u8>, Op, Layout)> {
+    OPCODE_TABLE
+        .iter()
+        .enumerate()
+        .flat_map(|(byte, entry)| -> Vec<(u8, Option<u8>, Op, Layout)> {
+            match entry {
+                Entry::Inst(op, layout) => vec![(byte as u8, None, *op, *layout)],
+                Entry::Group(group) => group
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(reg, entry)| match entry {
+                        Entry::Inst(op, layout) => {
+                            Some((byte as u8, Some(reg as u8), *op, *layout))
+                        }
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            }
+        })
+}
+
+/// Encodes every statement once, returning the bytes and where each label ended up
+fn encode_all(
+    statements: &[Statement],
+    labels: &HashMap<String, usize>,
+) -> Result<(Vec<u8>, HashMap<String, usize>), AssembleError> {
+    let mut bytes = Vec::new();
+    let mut placed = HashMap::new();
+
+    for statement in statements {
+        for label in &statement.labels {
+            placed.insert(label.clone(), bytes.len());
+        }
+
+        match &statement.body {
+            Body::Empty => {}
+            Body::Data(data) => bytes.extend_from_slice(data),
+            Body::Instruction(template) => {
+                let offset = bytes.len();
+                let encoded = encode(template, offset, labels).ok_or_else(|| AssembleError {
+                    line: statement.line,
+                    message: "no encoding matches the instruction and its operands".to_string(),
+                })?;
+
+                bytes.extend_from_slice(&encoded);
+            }
+        }
+    }
+
+    Ok((bytes, placed))
+}
+
+/// Picks the shortest encoding of `template` at `offset`
+fn encode(template: &Template, offset: usize, labels: &HashMap<String, usize>) -> Option<Vec<u8>> {
+    // Labels that haven't been placed yet are treated as pointing at the jump itself
+    let target = template
+        .operands
+        .iter()
+        .flatten()
+        .find_map(|argument| match argument {
+            Argument::Target(Target::Label(label)) => {
+                Some(labels.get(label).copied().unwrap_or(offset))
+            }
+            Argument::Target(Target::Absolute(target)) => Some(*target),
+            Argument::Target(Target::Here(relative)) => {
+                usize::try_from(offset as isize + relative).ok()
+            }
+            Argument::Operand(_) => None,
+        });
+
+    let candidates = |operands: [Option<Argument>; 2]| {
+        let template = Template {
+            operands,
+            ..template.clone()
+        };
+
+        table_entries()
+            .filter(|(_, _, op, _)| *op == template.op)
+            .filter_map(|(byte, group_reg, _, layout)| {
+                let bytes = encode_candidate(&template, byte, group_reg, layout, offset, target)?;
+                let decoded = decode(&bytes).ok()?;
+
+                let is_match =
+                    decoded.size == bytes.len() && matches(&template, &decoded, offset, target);
+
+                // Like NASM, prefer a sign extended byte immediate over the accumulator form
+                let rank = !matches!(layout, Layout::RmImmediate { sign_extend: true });
+                is_match.then_some((bytes.len(), rank, bytes))
+            })
+            .min_by_key(|(length, rank, _)| (*length, *rank))
+            .map(|(_, _, bytes)| bytes)
+    };
+
+    let [first, second] = template.operands.clone();
+    candidates([first.clone(), second.clone()]).or_else(|| {
+        // xchg and test only come in one operand order, but read the same either way around
+        match (template.op, &second) {
+            (Op::Xchg | Op::Test, Some(_)) => candidates([second, first]),
+            _ => None,
+        }
+    })
+}
+
+/// Whether the decoder reads `decoded` back as the instruction `template` describes
+fn matches(
+    template: &Template,
+    decoded: &Instruction,
+    offset: usize,
+    target: Option<usize>,
+) -> bool {
+    let operands_match =
+        template
+            .operands
+            .iter()
+            .zip(decoded.operands.iter())
+            .all(|(expected, actual)| match (expected, actual) {
+                (None, None) => true,
+                (Some(Argument::Operand(expected)), Some(actual)) => expected == actual,
+                (Some(Argument::Target(_)), Some(Operand::Relative(displacement))) => {
+                    let next = (offset + decoded.size) as isize;
+                    target.map(|target| target as isize) == Some(next + *displacement as isize)
+                }
+                _ => false,
+            });
+
+    // Direct far jumps are far by definition, whether or not the source says so
+    let is_far_pointer = matches!(decoded.operands[0], Some(Operand::FarPointer { .. }));
+
+    operands_match
+        && decoded.op == template.op
+        && (decoded.flags & FLAG_LOCK != 0) == template.is_lock
+        && decoded.flags & (FLAG_REP | FLAG_REPNE) == template.repeat
+        && decoded.segment_override == template.segment_override
+        && template
+            .is_wide
+            .is_none_or(|is_wide| decoded.is_wide() == is_wide)
+        && (is_far_pointer || decoded.is_far() == template.is_far)
+}
+
+/// Encodes `template` with a single table entry. The result still has to be checked by decoding
+/// it, this only lays the operands out the way `layout` expects them
+fn encode_candidate(
+    template: &Template,
+    byte: u8,
+    group_reg: Option<u8>,
+    layout: Layout,
+    offset: usize,
+    target: Option<usize>,
+) -> Option<Vec<u8>> {
+    let operand = |index: usize| match &template.operands[index] {
+        Some(Argument::Operand(operand)) => Some(*operand),
+        _ => None,
+    };
+    let immediate = |index: usize| match operand(index) {
+        Some(Operand::Immediate(value)) => Some(value),
+        _ => None,
+    };
+
+    let d_field = (byte >> 1) & 0b1 == 0b1;
+    let w_field = byte & 0b1 == 0b1;
+
+    let mut bytes = Vec::new();
+
+    if template.is_lock {
+        bytes.push(0xF0);
+    }
+
+    match template.repeat {
+        FLAG_REP => bytes.push(0xF3),
+        FLAG_REPNE => bytes.push(0xF2),
+        _ => {}
+    }
+
+    if let Some(segment) = template.segment_override {
+        bytes.push(match segment {
+            Reg::Es => 0x26,
+            Reg::Cs => 0x2E,
+            Reg::Ss => 0x36,
+            _ => 0x3E,
+        });
+    }
+
+    let prefix_length = bytes.len();
+
+    match layout {
+        Layout::Implied
+        | Layout::Register
+        | Layout::AccumulatorRegister
+        | Layout::SegmentRegister
+        | Layout::PortDx
+        | Layout::String => bytes.push(byte),
+
+        Layout::RegRm | Layout::SegmentRegisterRm => {
+            let (reg, rm) = match d_field {
+                true => (operand(0)?, operand(1)?),
+                false => (operand(1)?, operand(0)?),
+            };
+            let Operand::Register(reg) = reg else {
+                return None;
+            };
+
+            push_mod_rm(&mut bytes, byte, register_code(reg), rm)?;
+        }
+
+        Layout::LoadAddress => {
+            let Operand::Register(reg) = operand(0)? else {
+                return None;
+            };
+
+            push_mod_rm(&mut bytes, byte, register_code(reg), operand(1)?)?;
+        }
+
+        Layout::RmImmediate { sign_extend } => {
+            push_mod_rm(&mut bytes, byte, group_reg?, operand(0)?)?;
+
+            let value = immediate(1)?;
+            match sign_extend && w_field {
+                true => bytes.push(value as u8),
+                false => push_data(&mut bytes, value, w_field),
+            }
+        }
+
+        Layout::Rm | Layout::RmFar | Layout::Shift => {
+            push_mod_rm(&mut bytes, byte, group_reg?, operand(0)?)?;
+        }
+
+        Layout::RegImmediate => {
+            bytes.push(byte);
+            push_data(&mut bytes, immediate(1)?, (byte >> 3) & 0b1 == 0b1);
+        }
+
+        Layout::AccumulatorImmediate => {
+            bytes.push(byte);
+            push_data(&mut bytes, immediate(1)?, w_field);
+        }
+
+        Layout::AccumulatorMemory => {
+            let memory = match d_field {
+                true => operand(0)?,
+                false => operand(1)?,
+            };
+            let Operand::Memory(address) = memory else {
+                return None;
+            };
+
+            if address.base != AddressBase::Direct {
+                return None;
+            }
+
+            bytes.push(byte);
+            push_data(&mut bytes, address.displacement as u16, true);
+        }
+
+        Layout::PortImmediate => {
+            let port = immediate(0).or(immediate(1))?;
+            bytes.extend_from_slice(&[byte, port as u8]);
+        }
+
+        Layout::ShortJump => {
+            let next = (offset + prefix_length + 2) as isize;
+            let displacement = i8::try_from(target? as isize - next).ok()?;
+            bytes.extend_from_slice(&[byte, displacement as u8]);
+        }
+
+        Layout::NearJump => {
+            let next = (offset + prefix_length + 3) as isize;
+            bytes.push(byte);
+            push_data(&mut bytes, (target? as isize - next) as u16, true);
+        }
+
+        Layout::FarPointer => {
+            let Operand::FarPointer { segment, offset } = operand(0)? else {
+                return None;
+            };
+
+            bytes.push(byte);
+            push_data(&mut bytes, offset, true);
+            push_data(&mut bytes, segment, true);
+        }
+
+        Layout::Immediate8 => {
+            // aam and aad without an operand work in base 10
+            let value = match (template.op, &template.operands[0]) {
+                (Op::Aam | Op::Aad, None) => 10,
+                _ => immediate(0)?,
+            };
+
+            bytes.extend_from_slice(&[byte, value as u8]);
+        }
+
+        Layout::Immediate16 => {
+            bytes.push(byte);
+            push_data(&mut bytes, immediate(0)?, true);
+        }
+
+        Layout::Escape => {
+            let code = immediate(0)? as u8;
+            push_mod_rm(&mut bytes, byte, code & 0b111, operand(1)?)?;
+        }
+    }
+
+    Some(bytes)
+}
+
+/// Appends the opcode, the MOD REG R/M byte for `rm` and its displacement
+fn push_mod_rm(bytes: &mut Vec<u8>, byte: u8, reg_field: u8, rm: Operand) -> Option<()> {
+    bytes.push(byte);
+
+    match rm {
+        Operand::Register(reg) => bytes.push(0b11_000_000 | reg_field << 3 | register_code(reg)),
+        Operand::Memory(address) => {
+            let rm_field = match address.base {
+                AddressBase::BxSi => 0b000,
+                AddressBase::BxDi => 0b001,
+                AddressBase::BpSi => 0b010,
+                AddressBase::BpDi => 0b011,
+                AddressBase::Si => 0b100,
+                AddressBase::Di => 0b101,
+                AddressBase::Bp => 0b110,
+                AddressBase::Bx => 0b111,
+                AddressBase::Direct => {
+                    bytes.push(reg_field << 3 | 0b110);
+                    push_data(bytes, address.displacement as u16, true);
+                    return Some(());
+                }
+            };
+
+            // [bp] has no encoding without a displacement, that slot is taken by direct addresses
+            let displacement = address.displacement;
+            if displacement == 0 && address.base != AddressBase::Bp {
+                bytes.push(reg_field << 3 | rm_field);
+            } else if let Ok(displacement) = i8::try_from(displacement) {
+                bytes.push(0b01_000_000 | reg_field << 3 | rm_field);
+                bytes.push(displacement as u8);
+            } else {
+                bytes.push(0b10_000_000 | reg_field << 3 | rm_field);
+                push_data(bytes, displacement as u16, true);
+            }
+        }
+        _ => return None,
+    }
+
+    Some(())
+}
+
+fn push_data(bytes: &mut Vec<u8>, value: u16, is_wide: bool) {
+    match is_wide {
+        true => bytes.extend_from_slice(&value.to_le_bytes()),
+        false => bytes.push(value as u8),
+    }
+}
+
+/// The number a register is encoded as in the REG and R/M fields
+fn register_code(reg: Reg) -> u8 {
+    match reg {
+        Reg::Al | Reg::Ax | Reg::Es => 0,
+        Reg::Cl | Reg::Cx | Reg::Cs => 1,
+        Reg::Dl | Reg::Dx | Reg::Ss => 2,
+        Reg::Bl | Reg::Bx | Reg::Ds => 3,
+        Reg::Ah | Reg::Sp => 4,
+        Reg::Ch | Reg::Bp => 5,
+        Reg::Dh | Reg::Si => 6,
+        Reg::Bh | Reg::Di => 7,
+    }
+}
@@ -1,7 +1,12 @@
-use clap::Parser;
+use clap::{ArgAction, Parser, Subcommand};
+use sim8086::cycles::CpuModel;
+use sim8086::report::Format;
 
 #[derive(Debug, Parser)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(long, short = 'a', default_value = "./listing_37")]
     pub asm_bin_path: String,
 
@@ -10,4 +15,46 @@ pub struct Args {
 
     #[arg(long, short = 's', default_value = "false")]
     pub sim: bool,
+
+    /// Keep decoding after an undecodable byte, emitting it as a `db` line instead of stopping
+    #[arg(long, short = 'c', default_value = "false")]
+    pub continue_on_error: bool,
+
+    /// Stop simulating after this many instructions, in case the program never terminates
+    #[arg(long, short = 'm', default_value_t = sim8086::simulator::DEFAULT_MAX_INSTRUCTIONS)]
+    pub max_instructions: usize,
+
+    /// Annotate every simulated instruction with its estimated 8086 clock count
+    #[arg(long, default_value = "false")]
+    pub cycles: bool,
+
+    /// Chip to estimate clocks for with --cycles, either 8086 or 8088
+    #[arg(long, default_value = "8086")]
+    pub cpu: CpuModel,
+
+    /// Print an annotated hex listing with the offset and raw bytes of every instruction, followed
+    /// by a summary of the bytes that couldn't be decoded. Always keeps going past them, as with -c
+    #[arg(long, default_value = "false", conflicts_with_all = ["sim", "format"])]
+    pub listing: bool,
+
+    /// Print the result as text (NASM source), json or csv, with one record per instruction
+    #[arg(long, default_value = "text")]
+    pub format: Format,
+
+    /// Log progress to stderr, or with -vv also every decoded instruction
+    #[arg(long, short = 'v', action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Assemble NASM style source, like the output of the disassembler, into a flat binary
+    Assemble {
+        input: String,
+
+        #[arg(long, short = 'o', default_value = "./output_recent.bin")]
+        output: String,
+    },
+    /// Step through a binary interactively, inspecting and changing registers and memory as it runs
+    Debug { input: String },
 }
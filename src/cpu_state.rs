@@ -1,3 +1,74 @@
+use crate::cycles::CpuModel;
+use crate::instruction::{AddressBase, EffectiveAddress, Reg};
+use crate::memory::Memory;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Bit positions within the FLAGS register
+pub const CARRY_FLAG: u16 = 1 << 0;
+pub const PARITY_FLAG: u16 = 1 << 2;
+pub const AUXILIARY_CARRY_FLAG: u16 = 1 << 4;
+pub const ZERO_FLAG: u16 = 1 << 6;
+pub const SIGN_FLAG: u16 = 1 << 7;
+pub const TRAP_FLAG: u16 = 1 << 8;
+pub const INTERRUPT_FLAG: u16 = 1 << 9;
+pub const DIRECTION_FLAG: u16 = 1 << 10;
+pub const OVERFLOW_FLAG: u16 = 1 << 11;
+
+/// A single bit of the FLAGS register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Carry,
+    Parity,
+    AuxiliaryCarry,
+    Zero,
+    Sign,
+    Trap,
+    Interrupt,
+    Direction,
+    Overflow,
+}
+
+impl Flag {
+    /// The bit of the FLAGS register this flag lives in
+    pub fn mask(&self) -> u16 {
+        match self {
+            Flag::Carry => CARRY_FLAG,
+            Flag::Parity => PARITY_FLAG,
+            Flag::AuxiliaryCarry => AUXILIARY_CARRY_FLAG,
+            Flag::Zero => ZERO_FLAG,
+            Flag::Sign => SIGN_FLAG,
+            Flag::Trap => TRAP_FLAG,
+            Flag::Interrupt => INTERRUPT_FLAG,
+            Flag::Direction => DIRECTION_FLAG,
+            Flag::Overflow => OVERFLOW_FLAG,
+        }
+    }
+}
+
+/// Every flag paired with the letter used for it in traces, in bit order
+const FLAG_LETTERS: [(u16, char); 9] = [
+    (CARRY_FLAG, 'C'),
+    (PARITY_FLAG, 'P'),
+    (AUXILIARY_CARRY_FLAG, 'A'),
+    (ZERO_FLAG, 'Z'),
+    (SIGN_FLAG, 'S'),
+    (TRAP_FLAG, 'T'),
+    (INTERRUPT_FLAG, 'I'),
+    (DIRECTION_FLAG, 'D'),
+    (OVERFLOW_FLAG, 'O'),
+];
+
+/// Formats the set bits of a FLAGS value as letters, e.g. `CPZ`
+pub fn format_flags(flags: u16) -> String {
+    FLAG_LETTERS
+        .iter()
+        .filter(|(mask, _)| flags & mask != 0)
+        .map(|(_, letter)| letter)
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct Register {
     value: u16, // 8086 uses 16-bit registers
@@ -39,6 +110,30 @@ impl Register {
     }
 }
 
+impl Default for Register {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An interrupt service implemented in Rust. It's called with IP already past the instruction that
+/// raised the interrupt, and gets the memory as a separate argument because it is taken out of the
+/// CPU state for the duration of the call. `cpu_state.memory` is blank until the handler returns
+pub type IntHandler = Rc<dyn Fn(&mut CpuState, &mut Memory)>;
+
+/// The interrupt vectors that are serviced by an [`IntHandler`] instead of through the interrupt
+/// vector table
+#[derive(Default, Clone)]
+pub struct IntHandlers(HashMap<u8, IntHandler>);
+
+impl fmt::Debug for IntHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut vectors: Vec<&u8> = self.0.keys().collect();
+        vectors.sort();
+        f.debug_set().entries(vectors).finish()
+    }
+}
+
 #[derive(Debug)]
 pub struct CpuState {
     // General purpose registers
@@ -53,12 +148,33 @@ pub struct CpuState {
     pub bp: Register,
     pub sp: Register,
 
+    // Segment registers
+    pub cs: Register,
+    pub ds: Register,
+    pub es: Register,
+    pub ss: Register,
+
     // Instruction pointer
-    pub ip: i16,
+    pub ip: u16,
+
+    // Packed FLAGS register, see the *_FLAG constants for the layout
+    pub flags: u16,
+
+    // Clocks spent executing so far, `None` unless clock estimation was asked for
+    pub total_clocks: Option<u64>,
+    // Chip whose timings the clock estimates follow
+    pub cpu_model: CpuModel,
+
+    pub memory: Memory,
 
-    // Flags
-    pub sign_flag: bool,
-    pub zero_flag: bool,
+    // Interrupts serviced natively rather than by code in the simulated memory
+    int_handlers: IntHandlers,
+}
+
+impl Default for CpuState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CpuState {
@@ -74,96 +190,194 @@ impl CpuState {
             bp: Register::new(),
             sp: Register::new(),
 
-            ip: 0_i16,
+            cs: Register::new(),
+            ds: Register::new(),
+            es: Register::new(),
+            ss: Register::new(),
+
+            ip: 0_u16,
+
+            flags: 0,
+
+            total_clocks: None,
+            cpu_model: CpuModel::I8086,
 
-            sign_flag: false,
-            zero_flag: false,
+            memory: Memory::new(),
+
+            int_handlers: IntHandlers::default(),
         }
     }
 
+    /// Services interrupt `vector` with `handler` from now on, instead of jumping to whatever the
+    /// interrupt vector table points at. Nothing gets pushed on the stack for a handled interrupt,
+    /// so the program carries on after the `int` as if the handler had returned with `iret`.
+    /// Registering a vector a second time replaces the previous handler
+    pub fn register_int_handler<F>(&mut self, vector: u8, handler: F)
+    where
+        F: Fn(&mut CpuState, &mut Memory) + 'static,
+    {
+        self.int_handlers.0.insert(vector, Rc::new(handler));
+    }
+
+    /// The handler registered for interrupt `vector`, if any
+    pub fn int_handler(&self, vector: u8) -> Option<IntHandler> {
+        self.int_handlers.0.get(&vector).cloned()
+    }
+
     pub fn modify_ip(&mut self, value: i16) {
-        self.ip += value;
+        self.ip = self.ip.wrapping_add_signed(value);
     }
 
     /// Get value of the register
-    pub fn get_register_value(&self, register: &str) -> u16 {
+    pub fn get_register_value(&self, register: Reg) -> u16 {
         match register {
-            "al" => self.ax.get_low() as u16,
-            "ah" => self.ax.get_high() as u16,
-            "ax" => self.ax.get(),
-
-            "bl" => self.bx.get_low() as u16,
-            "bh" => self.bx.get_high() as u16,
-            "bx" => self.bx.get(),
-
-            "cl" => self.cx.get_low() as u16,
-            "ch" => self.cx.get_high() as u16,
-            "cx" => self.cx.get(),
-
-            "dl" => self.dx.get_low() as u16,
-            "dh" => self.dx.get_high() as u16,
-            "dx" => self.dx.get(),
+            Reg::Al => self.ax.get_low() as u16,
+            Reg::Ah => self.ax.get_high() as u16,
+            Reg::Ax => self.ax.get(),
+
+            Reg::Bl => self.bx.get_low() as u16,
+            Reg::Bh => self.bx.get_high() as u16,
+            Reg::Bx => self.bx.get(),
+
+            Reg::Cl => self.cx.get_low() as u16,
+            Reg::Ch => self.cx.get_high() as u16,
+            Reg::Cx => self.cx.get(),
+
+            Reg::Dl => self.dx.get_low() as u16,
+            Reg::Dh => self.dx.get_high() as u16,
+            Reg::Dx => self.dx.get(),
+
+            Reg::Si => self.si.get(),
+            Reg::Di => self.di.get(),
+            Reg::Bp => self.bp.get(),
+            Reg::Sp => self.sp.get(),
+
+            Reg::Cs => self.cs.get(),
+            Reg::Ds => self.ds.get(),
+            Reg::Es => self.es.get(),
+            Reg::Ss => self.ss.get(),
+        }
+    }
 
-            "si" => self.si.get(),
-            "di" => self.di.get(),
-            "bp" => self.bp.get(),
-            "sp" => self.sp.get(),
+    /// Computes the 16-bit offset a memory operand refers to from the current register values.
+    /// The sum wraps around at 64K like it does on the 8086
+    pub fn effective_address(&self, address: &EffectiveAddress) -> u16 {
+        let base = match address.base {
+            AddressBase::BxSi => self.bx.get().wrapping_add(self.si.get()),
+            AddressBase::BxDi => self.bx.get().wrapping_add(self.di.get()),
+            AddressBase::BpSi => self.bp.get().wrapping_add(self.si.get()),
+            AddressBase::BpDi => self.bp.get().wrapping_add(self.di.get()),
+            AddressBase::Si => self.si.get(),
+            AddressBase::Di => self.di.get(),
+            AddressBase::Bp => self.bp.get(),
+            AddressBase::Bx => self.bx.get(),
+            AddressBase::Direct => 0,
+        };
+
+        base.wrapping_add_signed(address.displacement)
+    }
 
-            _ => panic!("Unknown register: {}", register),
-        }
+    /// Forms the 20-bit address a memory operand refers to, using `segment_override` or the
+    /// default segment for its addressing mode
+    pub fn physical_address(
+        &self,
+        address: &EffectiveAddress,
+        segment_override: Option<Reg>,
+    ) -> u32 {
+        let segment = segment_override.unwrap_or(address.base.default_segment());
+        segmented_address(
+            self.get_register_value(segment),
+            self.effective_address(address),
+        )
     }
 
-    pub fn get_ip(&self) -> i16 {
+    pub fn get_ip(&self) -> u16 {
         self.ip
     }
 
     /// Set new value for the register
-    pub fn set_new_register_value(&mut self, register: &str, value: u16) {
+    pub fn set_new_register_value(&mut self, register: Reg, value: u16) {
         match register {
-            "al" => self.ax.set_low(value as u8),
-            "ah" => self.ax.set_high(value as u8),
-            "ax" => self.ax.set(value),
+            Reg::Al => self.ax.set_low(value as u8),
+            Reg::Ah => self.ax.set_high(value as u8),
+            Reg::Ax => self.ax.set(value),
+
+            Reg::Bl => self.bx.set_low(value as u8),
+            Reg::Bh => self.bx.set_high(value as u8),
+            Reg::Bx => self.bx.set(value),
+
+            Reg::Cl => self.cx.set_low(value as u8),
+            Reg::Ch => self.cx.set_high(value as u8),
+            Reg::Cx => self.cx.set(value),
+
+            Reg::Dl => self.dx.set_low(value as u8),
+            Reg::Dh => self.dx.set_high(value as u8),
+            Reg::Dx => self.dx.set(value),
+
+            Reg::Si => self.si.set(value),
+            Reg::Di => self.di.set(value),
+            Reg::Bp => self.bp.set(value),
+            Reg::Sp => self.sp.set(value),
+
+            Reg::Cs => self.cs.set(value),
+            Reg::Ds => self.ds.set(value),
+            Reg::Es => self.es.set(value),
+            Reg::Ss => self.ss.set(value),
+        }
+    }
 
-            "bl" => self.bx.set_low(value as u8),
-            "bh" => self.bx.set_high(value as u8),
-            "bx" => self.bx.set(value),
+    pub fn print_register_state(&self) {
+        print!("{}", self.register_state());
+    }
 
-            "cl" => self.cx.set_low(value as u8),
-            "ch" => self.cx.set_high(value as u8),
-            "cx" => self.cx.set(value),
+    /// Every register, IP and FLAGS on a line of their own, plus the clock count if there is one
+    pub fn register_state(&self) -> String {
+        let registers = [
+            ("ax", self.ax.get()),
+            ("bx", self.bx.get()),
+            ("cx", self.cx.get()),
+            ("dx", self.dx.get()),
+            ("sp", self.sp.get()),
+            ("bp", self.bp.get()),
+            ("si", self.si.get()),
+            ("di", self.di.get()),
+            ("es", self.es.get()),
+            ("cs", self.cs.get()),
+            ("ss", self.ss.get()),
+            ("ds", self.ds.get()),
+            ("ip", self.ip),
+        ];
+
+        let mut state: String = registers
+            .iter()
+            .map(|(name, value)| format!("{}: {:#X} ({})\n", name, value, value))
+            .collect();
+
+        state.push_str(&format!("flags: {}\n", format_flags(self.flags)));
+
+        if let Some(total_clocks) = self.total_clocks {
+            state.push_str(&format!("clocks: {}\n", total_clocks));
+        }
 
-            "dl" => self.dx.set_low(value as u8),
-            "dh" => self.dx.set_high(value as u8),
-            "dx" => self.dx.set(value),
+        state
+    }
 
-            "si" => self.si.set(value),
-            "di" => self.di.set(value),
-            "bp" => self.bp.set(value),
-            "sp" => self.sp.set(value),
+    pub fn get_flag(&self, flag: Flag) -> bool {
+        self.flags & flag.mask() != 0
+    }
 
-            "ip" => panic!("Cannot set IP directly, use modify_ip() instead"),
+    pub fn set_flag(&mut self, flag: Flag, value: bool) {
+        let mask = flag.mask();
 
-            _ => panic!("Unknown register: {}", register),
+        match value {
+            true => self.flags |= mask,
+            false => self.flags &= !mask,
         }
     }
+}
 
-    pub fn print_register_state(&self) {
-        println!("ax: {:#X} ({})", self.ax.get(), self.ax.get());
-        println!("bx: {:#X} ({})", self.bx.get(), self.bx.get());
-        println!("cx: {:#X} ({})", self.cx.get(), self.cx.get());
-        println!("dx: {:#X} ({})", self.dx.get(), self.dx.get());
-        println!("sp: {:#X} ({})", self.sp.get(), self.sp.get());
-        println!("bp: {:#X} ({})", self.bp.get(), self.bp.get());
-        println!("si: {:#X} ({})", self.si.get(), self.si.get());
-        println!("di: {:#X} ({})", self.di.get(), self.di.get());
-        println!("ip: ({})", self.ip);
-    }
-
-    pub fn set_flag(&mut self, flag: &str, value: bool) {
-        match flag {
-            "sign" => self.sign_flag = value,
-            "zero" => self.zero_flag = value,
-            _ => panic!("Unknown flag: {}", flag),
-        }
-    }
+/// Combines a segment and an offset into a 20-bit physical address. Anything past 1 MiB wraps
+/// back around to 0
+pub fn segmented_address(segment: u16, offset: u16) -> u32 {
+    (((segment as u32) << 4) + offset as u32) & 0xF_FFFF
 }
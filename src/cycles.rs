@@ -0,0 +1,425 @@
+//! Clock count estimates for the 8086, following the timing tables in the 8086 family user's
+//! manual.
+//!
+//! Instructions with a range of timings (mul/div) use the lowest figure, and the clocks spent
+//! waiting on the prefetch queue or the bus are not modelled.
+
+use crate::instruction::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// Which chip to estimate clocks for. The 8088 is an 8086 with an 8-bit data bus, so every word it
+/// moves to or from memory takes two bus cycles
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CpuModel {
+    #[default]
+    I8086,
+    I8088,
+}
+
+impl FromStr for CpuModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8086" => Ok(CpuModel::I8086),
+            "8088" => Ok(CpuModel::I8088),
+            _ => Err(format!("unknown CPU {:?}, expected 8086 or 8088", s)),
+        }
+    }
+}
+
+/// Extra clocks for every word transferred over a bus that can't move it in one go
+const TRANSFER_PENALTY: u32 = 4;
+
+/// Clocks a REP prefix takes on top of the iterations
+const REP_SETUP: u32 = 9;
+
+/// Clocks spent on a single instruction, split the way the manual lists them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Clocks {
+    /// Clocks for the operation itself
+    pub base: u32,
+    /// Clocks spent calculating the effective address of the memory operand
+    pub ea: u32,
+    /// Clocks lost to word transfers the bus has to split in two
+    pub penalty: u32,
+}
+
+impl Clocks {
+    pub fn total(&self) -> u32 {
+        self.base + self.ea + self.penalty
+    }
+}
+
+impl fmt::Display for Clocks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.base)?;
+
+        if self.ea != 0 {
+            write!(f, " + {}ea", self.ea)?;
+        }
+
+        if self.penalty != 0 {
+            write!(f, " + {}p", self.penalty)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Rough shape of an operand, which is all the timing tables care about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Missing,
+    Accumulator,
+    Register,
+    Segment,
+    Memory,
+    Immediate,
+    Other,
+}
+
+fn kind(operand: Option<Operand>) -> Kind {
+    match operand {
+        None => Kind::Missing,
+        Some(Operand::Register(Reg::Al | Reg::Ax)) => Kind::Accumulator,
+        Some(Operand::Register(reg)) if reg.is_segment() => Kind::Segment,
+        Some(Operand::Register(_)) => Kind::Register,
+        Some(Operand::Memory(_)) => Kind::Memory,
+        Some(Operand::Immediate(_)) => Kind::Immediate,
+        Some(_) => Kind::Other,
+    }
+}
+
+/// Clocks the 8086 needs to compute an effective address
+pub fn ea_clocks(address: &EffectiveAddress) -> u32 {
+    // [bp] can only be encoded with a displacement, even if it's 0
+    let has_displacement = address.displacement != 0 || address.base == AddressBase::Bp;
+
+    match (address.base, has_displacement) {
+        (AddressBase::Direct, _) => 6,
+        (AddressBase::Si | AddressBase::Di | AddressBase::Bp | AddressBase::Bx, false) => 5,
+        (AddressBase::Si | AddressBase::Di | AddressBase::Bp | AddressBase::Bx, true) => 9,
+        (AddressBase::BpDi | AddressBase::BxSi, false) => 7,
+        (AddressBase::BpSi | AddressBase::BxDi, false) => 8,
+        (AddressBase::BpDi | AddressBase::BxSi, true) => 11,
+        (AddressBase::BpSi | AddressBase::BxDi, true) => 12,
+    }
+}
+
+/// Estimates the clocks taken by `instruction` on `model`. `jump_taken` tells whether a
+/// conditional jump or loop transferred control, `shift_count` is the value of CL for shifts and
+/// rotates by CL, and `effective_address` is the offset of the memory operand, if there is one.
+pub fn estimate(
+    instruction: &Instruction,
+    model: CpuModel,
+    jump_taken: bool,
+    shift_count: u8,
+    effective_address: Option<u16>,
+) -> Clocks {
+    use Kind::*;
+
+    let [destination, source] = instruction.operands;
+    let (destination, source) = (kind(destination), kind(source));
+    let is_wide = instruction.is_wide();
+    let is_far = instruction.is_far();
+
+    let address = instruction
+        .operands
+        .iter()
+        .flatten()
+        .find_map(|operand| match operand {
+            Operand::Memory(address) => Some(*address),
+            _ => None,
+        });
+
+    // Assemblers always pick the short accumulator form of mov to or from a direct address, which
+    // has the address built in instead of computing it
+    let accumulator_direct = instruction.op == Op::Mov
+        && matches!(
+            (destination, source),
+            (Accumulator, Memory) | (Memory, Accumulator)
+        )
+        && matches!(address, Some(address) if address.base == AddressBase::Direct);
+
+    let ea = match address {
+        Some(address) if !accumulator_direct => ea_clocks(&address),
+        _ => 0,
+    };
+
+    // Going through another segment than the default one costs 2 more on any memory operand
+    let ea = match (address, instruction.segment_override) {
+        (Some(_), Some(_)) => ea + 2,
+        _ => ea,
+    };
+
+    let base = match instruction.op {
+        Op::Mov => match (destination, source) {
+            _ if accumulator_direct => 10,
+            (Memory, _) if source != Immediate => 9,
+            (_, Memory) => 8,
+            (Memory, Immediate) => 10,
+            (_, Immediate) => 4,
+            _ => 2,
+        },
+
+        Op::Add | Op::Adc | Op::Sub | Op::Sbb | Op::And | Op::Or | Op::Xor => {
+            match (destination, source) {
+                (Memory, Immediate) => 17,
+                (Memory, _) => 16,
+                (_, Memory) => 9,
+                (_, Immediate) => 4,
+                _ => 3,
+            }
+        }
+        Op::Cmp => match (destination, source) {
+            (Memory, Immediate) => 10,
+            (Memory, _) | (_, Memory) => 9,
+            (_, Immediate) => 4,
+            _ => 3,
+        },
+        Op::Test => match (destination, source) {
+            (Memory, Immediate) => 11,
+            (Memory, _) | (_, Memory) => 9,
+            (Accumulator, Immediate) => 4,
+            (_, Immediate) => 5,
+            _ => 3,
+        },
+
+        Op::Inc | Op::Dec => match destination {
+            Memory => 15,
+            _ if is_wide => 2,
+            _ => 3,
+        },
+        Op::Neg | Op::Not => match destination {
+            Memory => 16,
+            _ => 3,
+        },
+
+        Op::Shl | Op::Shr | Op::Sar | Op::Rol | Op::Ror | Op::Rcl | Op::Rcr => {
+            let by_cl = matches!(instruction.operands[1], Some(Operand::Register(Reg::Cl)));
+
+            match (destination, by_cl) {
+                (Memory, true) => 20 + 4 * shift_count as u32,
+                (Memory, false) => 15,
+                (_, true) => 8 + 4 * shift_count as u32,
+                (_, false) => 2,
+            }
+        }
+
+        Op::Mul => multiply_or_divide(destination, is_wide, 70, 118),
+        Op::Imul => multiply_or_divide(destination, is_wide, 80, 128),
+        Op::Div => multiply_or_divide(destination, is_wide, 80, 144),
+        Op::Idiv => multiply_or_divide(destination, is_wide, 101, 165),
+
+        Op::Xchg => match (destination, source) {
+            (Memory, _) | (_, Memory) => 17,
+            (Accumulator, Register) if is_wide => 3,
+            _ => 4,
+        },
+        Op::Lea => 2,
+        Op::Lds | Op::Les => 16,
+
+        Op::Push => match destination {
+            Memory => 16,
+            Segment => 10,
+            _ => 11,
+        },
+        Op::Pop => match destination {
+            Memory => 17,
+            _ => 8,
+        },
+        Op::Pushf => 10,
+        Op::Popf => 8,
+        Op::Lahf | Op::Sahf => 4,
+        Op::Xlat => 11,
+        Op::In | Op::Out => match (destination, source) {
+            (Immediate, _) | (_, Immediate) => 10,
+            _ => 8,
+        },
+
+        Op::Cbw => 2,
+        Op::Cwd => 5,
+        Op::Aaa | Op::Aas | Op::Daa | Op::Das => 4,
+        Op::Aam => 83,
+        Op::Aad => 60,
+
+        Op::Jmp => match destination {
+            Memory if is_far => 24,
+            Memory => 18,
+            Register | Accumulator => 11,
+            _ => 15,
+        },
+        Op::Call => match destination {
+            Memory if is_far => 37,
+            Memory => 21,
+            Register | Accumulator => 16,
+            Other if matches!(instruction.operands[0], Some(Operand::FarPointer { .. })) => 28,
+            _ => 19,
+        },
+        Op::Ret => match destination {
+            Immediate => 12,
+            _ => 8,
+        },
+        Op::Retf => match destination {
+            Immediate => 17,
+            _ => 18,
+        },
+
+        Op::Jo
+        | Op::Jno
+        | Op::Jb
+        | Op::Jnb
+        | Op::Je
+        | Op::Jne
+        | Op::Jbe
+        | Op::Ja
+        | Op::Js
+        | Op::Jns
+        | Op::Jp
+        | Op::Jnp
+        | Op::Jl
+        | Op::Jnl
+        | Op::Jle
+        | Op::Jg => taken_or_not(jump_taken, 16, 4),
+        Op::Loop => taken_or_not(jump_taken, 17, 5),
+        Op::Loopz => taken_or_not(jump_taken, 18, 6),
+        Op::Loopnz => taken_or_not(jump_taken, 19, 5),
+        Op::Jcxz => taken_or_not(jump_taken, 18, 6),
+
+        Op::Int => 51,
+        Op::Int3 => 52,
+        Op::Into => taken_or_not(jump_taken, 53, 4),
+        Op::Iret => 24,
+
+        // Repeated string instructions are timed a step at a time by `string_clocks`
+        Op::Movs | Op::Cmps | Op::Scas | Op::Lods | Op::Stos => string_base(instruction.op, false),
+
+        Op::Clc | Op::Cmc | Op::Stc | Op::Cld | Op::Std | Op::Cli | Op::Sti | Op::Hlt => 2,
+        Op::Wait | Op::Nop => 3,
+        Op::Esc => match source {
+            Memory => 8,
+            _ => 2,
+        },
+    };
+
+    // Words are only ever split on the 8088, or on the 8086 when they straddle two bus words
+    let split_words = match (model, effective_address) {
+        (_, None) => false,
+        (CpuModel::I8088, Some(_)) => true,
+        (CpuModel::I8086, Some(offset)) => offset & 1 == 1,
+    };
+
+    let penalty = match is_wide && split_words {
+        true => TRANSFER_PENALTY * memory_transfers(instruction, destination),
+        false => 0,
+    };
+
+    Clocks { base, ea, penalty }
+}
+
+/// Estimates the clocks for one step of a string instruction, with `si` and `di` as they were
+/// before it ran. A repeated instruction is simulated one iteration per step: each step that moves
+/// data pays for an iteration, and the step that ends the repetition pays for setting it up. When
+/// CX is 0 to begin with, `iterated` is false and only the setup is paid for.
+pub fn string_clocks(
+    instruction: &Instruction,
+    model: CpuModel,
+    si: u16,
+    di: u16,
+    iterated: bool,
+    finished: bool,
+) -> Clocks {
+    let repeated = instruction.is_repeated();
+
+    let base = match (iterated, repeated && finished) {
+        (true, true) => REP_SETUP + string_base(instruction.op, true),
+        (true, false) => string_base(instruction.op, repeated),
+        (false, _) => REP_SETUP,
+    };
+
+    // DS:SI is read by movs, cmps and lods, ES:DI is read or written by all but lods
+    let offsets = match instruction.op {
+        Op::Movs | Op::Cmps => vec![si, di],
+        Op::Lods => vec![si],
+        _ => vec![di],
+    };
+
+    let split_words = offsets
+        .iter()
+        .filter(|offset| model == CpuModel::I8088 || *offset & 1 == 1)
+        .count() as u32;
+
+    let penalty = match instruction.is_wide() && iterated {
+        true => TRANSFER_PENALTY * split_words,
+        false => 0,
+    };
+
+    Clocks {
+        base,
+        ea: 0,
+        penalty,
+    }
+}
+
+/// Clocks for a single string instruction, or for one iteration of a repeated one
+fn string_base(op: Op, repeated: bool) -> u32 {
+    match (op, repeated) {
+        (Op::Movs, false) => 18,
+        (Op::Movs, true) => 17,
+        (Op::Cmps, _) => 22,
+        (Op::Scas, _) => 15,
+        (Op::Lods, false) => 12,
+        (Op::Lods, true) => 13,
+        (Op::Stos, false) => 11,
+        (Op::Stos, true) => 10,
+        _ => unreachable!("{:?} isn't a string instruction", op),
+    }
+}
+
+/// Number of times the instruction reads or writes its memory operand. Operations that modify the
+/// operand in place both read and write it
+fn memory_transfers(instruction: &Instruction, destination: Kind) -> u32 {
+    match instruction.op {
+        Op::Add | Op::Adc | Op::Sub | Op::Sbb | Op::And | Op::Or | Op::Xor
+            if destination == Kind::Memory =>
+        {
+            2
+        }
+        Op::Inc
+        | Op::Dec
+        | Op::Neg
+        | Op::Not
+        | Op::Shl
+        | Op::Shr
+        | Op::Sar
+        | Op::Rol
+        | Op::Ror
+        | Op::Rcl
+        | Op::Rcr
+        | Op::Xchg => 2,
+        // Far pointers are two words
+        Op::Lds | Op::Les => 2,
+        Op::Jmp | Op::Call if instruction.is_far() => 2,
+        Op::Lea => 0,
+        _ => 1,
+    }
+}
+
+/// mul/imul/div/idiv take the same time for every register, and 6 clocks more from memory
+fn multiply_or_divide(destination: Kind, is_wide: bool, byte_clocks: u32, word_clocks: u32) -> u32 {
+    let clocks = if is_wide { word_clocks } else { byte_clocks };
+
+    match destination {
+        Kind::Memory => clocks + 6,
+        _ => clocks,
+    }
+}
+
+fn taken_or_not(taken: bool, taken_clocks: u32, not_taken_clocks: u32) -> u32 {
+    match taken {
+        true => taken_clocks,
+        false => not_taken_clocks,
+    }
+}
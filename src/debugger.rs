@@ -0,0 +1,306 @@
+//! Interactive step debugger over a [`CpuState`].
+//!
+//! Commands are handled one line at a time and their output is returned as text, so the loop that
+//! reads from the terminal lives in the binary and the commands themselves can be driven from
+//! anywhere.
+
+use crate::assembler::{parse_number, parse_register};
+use crate::cpu_state::{format_flags, segmented_address, CpuState};
+use crate::instruction::{Op, Reg};
+use crate::report::format_bytes;
+use crate::simulator::{fetch_at, load, push_trace, step, StopReason, DEFAULT_MAX_INSTRUCTIONS};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+pub const HELP: &str = "\
+step [n]             execute the next n instructions (default 1)
+continue             run until a breakpoint, hlt or the end of the program
+break [addr]         stop before executing the instruction at addr, or list breakpoints
+delete <addr>        remove the breakpoint at addr
+regs                 print the registers and flags
+mem <seg:off> [len]  dump len bytes of memory (default 64), seg defaults to ds
+set <reg> <value>    change a register, ip or flags
+disasm [addr] [n]    disassemble n instructions starting at addr (default ip, 5)
+quit                 leave the debugger
+";
+
+/// What the REPL should do after a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// Print the text and wait for the next command
+    Output(String),
+    Quit,
+}
+
+pub struct Debugger<'a> {
+    pub cpu_state: CpuState,
+    program: &'a [u8],
+    breakpoints: BTreeSet<u16>,
+    /// Cap on the number of instructions a single `continue` executes
+    max_instructions: usize,
+}
+
+impl<'a> Debugger<'a> {
+    /// Loads `program` into `cpu_state`, ready to run from its first instruction
+    pub fn new(mut cpu_state: CpuState, program: &'a [u8]) -> Self {
+        load(&mut cpu_state, program);
+
+        Debugger {
+            cpu_state,
+            program,
+            breakpoints: BTreeSet::new(),
+            max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+        }
+    }
+
+    pub fn with_max_instructions(mut self, max_instructions: usize) -> Self {
+        self.max_instructions = max_instructions;
+        self
+    }
+
+    /// Runs a single command line. Errors are messages meant for the user, like a mistyped
+    /// command or an address that doesn't parse
+    pub fn command(&mut self, line: &str) -> Result<Response, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, arguments)) = words.split_first() else {
+            return Ok(Response::Output(String::new()));
+        };
+
+        let output = match (command, arguments) {
+            ("step" | "s", []) => self.step(1),
+            ("step" | "s", [count]) => self.step(parse_count(count)?),
+            ("continue" | "c", []) => self.continue_running(),
+            ("break" | "b", []) => self.list_breakpoints(),
+            ("break" | "b", [address]) => {
+                let address = parse_u16(address)?;
+                self.breakpoints.insert(address);
+                format!("Breakpoint at {:04X}\n", address)
+            }
+            ("delete", [address]) => {
+                let address = parse_u16(address)?;
+                match self.breakpoints.remove(&address) {
+                    true => format!("Deleted breakpoint at {:04X}\n", address),
+                    false => return Err(format!("no breakpoint at {:04X}", address)),
+                }
+            }
+            ("regs" | "r", []) => self.cpu_state.register_state(),
+            ("mem" | "m", [address]) => self.dump_memory(address, 64)?,
+            ("mem" | "m", [address, length]) => self.dump_memory(address, parse_count(length)?)?,
+            ("set", [target, value]) => self.set(target, parse_u16(value)?)?,
+            ("disasm" | "d", []) => self.disassemble(self.cpu_state.get_ip(), 5),
+            ("disasm" | "d", [address]) => self.disassemble(parse_u16(address)?, 5),
+            ("disasm" | "d", [address, count]) => {
+                self.disassemble(parse_u16(address)?, parse_count(count)?)
+            }
+            ("help" | "h" | "?", []) => HELP.to_string(),
+            ("quit" | "q" | "exit", []) => return Ok(Response::Quit),
+            _ => {
+                return Err(format!(
+                    "can't make sense of {:?}, try `help` for a list of commands",
+                    line.trim()
+                ))
+            }
+        };
+
+        Ok(Response::Output(output))
+    }
+
+    /// The instruction IP points at, the way every stop is reported
+    pub fn current_instruction(&self) -> String {
+        self.disassemble(self.cpu_state.get_ip(), 1)
+    }
+
+    fn step(&mut self, count: usize) -> String {
+        let mut output = String::new();
+
+        for _ in 0..count {
+            let ip = self.cpu_state.get_ip();
+
+            match step(&mut self.cpu_state, self.program) {
+                Ok((instruction, trace)) => {
+                    writeln!(output, "{:04X}: {}", ip, instruction).unwrap();
+                    push_trace(&mut output, &self.cpu_state, &trace);
+
+                    if instruction.op == Op::Hlt {
+                        output.push_str("Halted\n");
+                        return output;
+                    }
+                }
+                Err(reason) => {
+                    writeln!(output, "{}", describe_stop(&reason)).unwrap();
+                    return output;
+                }
+            }
+        }
+
+        output.push_str(&self.current_instruction());
+        output
+    }
+
+    fn continue_running(&mut self) -> String {
+        let mut executed = 0;
+
+        let stop = loop {
+            if executed == self.max_instructions {
+                break format!("Stopped after {} instructions\n", executed);
+            }
+
+            match step(&mut self.cpu_state, self.program) {
+                Ok((instruction, _)) if instruction.op == Op::Hlt => break "Halted\n".to_string(),
+                Ok(_) => executed += 1,
+                Err(reason) => return format!("{}\n", describe_stop(&reason)),
+            }
+
+            if self.breakpoints.contains(&self.cpu_state.get_ip()) {
+                break format!("Breakpoint at {:04X}\n", self.cpu_state.get_ip());
+            }
+        };
+
+        stop + &self.current_instruction()
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "No breakpoints\n".to_string();
+        }
+
+        self.breakpoints
+            .iter()
+            .map(|address| format!("{:04X}\n", address))
+            .collect()
+    }
+
+    /// Hex dump of memory, 16 bytes to a line
+    fn dump_memory(&self, address: &str, length: usize) -> Result<String, String> {
+        let (segment, offset) = match address.split_once(':') {
+            Some((segment, offset)) => {
+                (parse_segment(&self.cpu_state, segment)?, parse_u16(offset)?)
+            }
+            None => (
+                self.cpu_state.get_register_value(Reg::Ds),
+                parse_u16(address)?,
+            ),
+        };
+
+        let mut output = String::new();
+
+        for line_start in (0..length).step_by(16) {
+            let line_offset = offset.wrapping_add(line_start as u16);
+            let bytes: Vec<u8> = (line_start..length.min(line_start + 16))
+                .map(|i| {
+                    let address = segmented_address(segment, offset.wrapping_add(i as u16));
+                    self.cpu_state.memory.read_u8(address)
+                })
+                .collect();
+
+            writeln!(
+                output,
+                "{:04X}:{:04X}  {}",
+                segment,
+                line_offset,
+                format_bytes(&bytes)
+            )
+            .unwrap();
+        }
+
+        Ok(output)
+    }
+
+    fn set(&mut self, target: &str, value: u16) -> Result<String, String> {
+        let target = target.to_ascii_lowercase();
+        match target.as_str() {
+            "ip" => self.cpu_state.ip = value,
+            "flags" => self.cpu_state.flags = value,
+            name => {
+                let reg = parse_register(name).ok_or(format!("unknown register {:?}", name))?;
+                self.cpu_state.set_new_register_value(reg, value);
+            }
+        }
+
+        Ok(match target.as_str() {
+            "flags" => format!("flags: {}\n", format_flags(value)),
+            _ => format!("{}: {:#X} ({})\n", target, value, value),
+        })
+    }
+
+    /// Lists `count` instructions of the program starting at CS:`address`, marking the one at IP
+    fn disassemble(&self, address: u16, count: usize) -> String {
+        let mut output = String::new();
+        let mut offset = address;
+        let cs = self.cpu_state.get_register_value(Reg::Cs);
+
+        for _ in 0..count {
+            let marker = match offset == self.cpu_state.get_ip() {
+                true => "=>",
+                false => "  ",
+            };
+
+            match fetch_at(&self.cpu_state, self.program, offset) {
+                Ok(instruction) => {
+                    let bytes: Vec<u8> = (0..instruction.size as u16)
+                        .map(|i| {
+                            let address = segmented_address(cs, offset.wrapping_add(i));
+                            self.cpu_state.memory.read_u8(address)
+                        })
+                        .collect();
+
+                    writeln!(
+                        output,
+                        "{} {:04X}: {:<17} {}",
+                        marker,
+                        offset,
+                        format_bytes(&bytes),
+                        instruction
+                    )
+                    .unwrap();
+                    offset = offset.wrapping_add(instruction.size as u16);
+                }
+                Err(StopReason::DecodeError(error)) => {
+                    writeln!(output, "{} {:04X}: {}", marker, offset, error).unwrap();
+                    break;
+                }
+                Err(_) => {
+                    output.push_str("   (end of program)\n");
+                    break;
+                }
+            }
+        }
+
+        output
+    }
+}
+
+fn describe_stop(reason: &StopReason) -> String {
+    match reason {
+        StopReason::EndOfProgram => "End of program".to_string(),
+        StopReason::Halted => "Halted".to_string(),
+        StopReason::InstructionLimit => "Instruction limit reached".to_string(),
+        StopReason::DecodeError(error) => format!("Decode error: {}", error),
+        StopReason::Unsupported(op) => {
+            format!("{} isn't supported by the simulator", op.mnemonic())
+        }
+    }
+}
+
+/// Parses a number that fits in 16 bits. Negative numbers are stored as two's complement
+fn parse_u16(text: &str) -> Result<u16, String> {
+    match parse_number(text) {
+        Some(value @ -0x8000..=0xFFFF) => Ok(value as u16),
+        Some(_) => Err(format!("{} doesn't fit in 16 bits", text)),
+        None => Err(format!("{:?} isn't a number", text)),
+    }
+}
+
+fn parse_count(text: &str) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| format!("{:?} isn't a count", text))
+}
+
+/// A segment is either a segment register or a number
+fn parse_segment(cpu_state: &CpuState, text: &str) -> Result<u16, String> {
+    match parse_register(text) {
+        Some(reg) if reg.is_segment() => Ok(cpu_state.get_register_value(reg)),
+        Some(reg) => Err(format!("{} isn't a segment register", reg)),
+        None => parse_u16(text),
+    }
+}
@@ -0,0 +1,494 @@
+use crate::instruction::*;
+use crate::opcode_table::{Entry, Layout, Prefix, OPCODE_TABLE};
+use std::fmt;
+
+/// Reasons an instruction could not be decoded. Every variant carries the offset of the first
+/// byte of the offending instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The buffer ended in the middle of an instruction that needs at least `needed` bytes
+    TruncatedInstruction { offset: usize, needed: usize },
+    /// The first byte doesn't match any instruction the decoder knows about
+    UnknownOpcode { offset: usize, byte: u8 },
+    /// The opcode is known, but its MOD REG R/M byte selects an encoding that doesn't exist
+    InvalidModRm { offset: usize, byte: u8, mod_rm: u8 },
+}
+
+impl DecodeError {
+    /// Offset of the first byte of the instruction that failed to decode
+    pub fn offset(&self) -> usize {
+        match *self {
+            DecodeError::TruncatedInstruction { offset, .. } => offset,
+            DecodeError::UnknownOpcode { offset, .. } => offset,
+            DecodeError::InvalidModRm { offset, .. } => offset,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TruncatedInstruction { offset, needed } => write!(
+                f,
+                "truncated instruction at offset {:#06X}, needs at least {} bytes",
+                offset, needed
+            ),
+            DecodeError::UnknownOpcode { offset, byte } => {
+                write!(f, "unknown opcode {:#04X} at offset {:#06X}", byte, offset)
+            }
+            DecodeError::InvalidModRm {
+                offset,
+                byte,
+                mod_rm,
+            } => write!(
+                f,
+                "invalid MOD REG R/M byte {:#04X} for opcode {:#04X} at offset {:#06X}",
+                mod_rm, byte, offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Cursor over the bytes of the instruction currently being decoded
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    /// Offset of the first byte of the instruction
+    start: usize,
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8], start: usize) -> Self {
+        ByteReader {
+            bytes,
+            start,
+            position: start,
+        }
+    }
+
+    /// Number of bytes consumed so far
+    fn len(&self) -> usize {
+        self.position - self.start
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        let taken = self.bytes.get(self.position..self.position + count).ok_or(
+            DecodeError::TruncatedInstruction {
+                offset: self.start,
+                needed: self.len() + count,
+            },
+        )?;
+        self.position += count;
+        Ok(taken)
+    }
+
+    fn next_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn next_u16(&mut self) -> Result<u16, DecodeError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Reads an 8-bit or 16-bit immediate depending on the W field
+    fn next_data(&mut self, is_wide: bool) -> Result<u16, DecodeError> {
+        match is_wide {
+            true => self.next_u16(),
+            false => Ok(self.next_u8()? as u16),
+        }
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`
+pub fn decode(bytes: &[u8]) -> Result<Instruction, DecodeError> {
+    decode_at(bytes, 0)
+}
+
+/// Decodes the instruction starting at `bytes[offset]`. Errors report offsets relative to the
+/// start of `bytes`
+pub fn decode_at(bytes: &[u8], offset: usize) -> Result<Instruction, DecodeError> {
+    let mut reader = ByteReader::new(bytes, offset);
+    let mut prefix_flags = 0;
+    let mut segment_override = None;
+
+    let mut byte = reader.next_u8()?;
+    let mut entry = OPCODE_TABLE[byte as usize];
+
+    // Prefixes only tweak the instruction that follows them
+    while let Entry::Prefix(prefix) = entry {
+        match prefix {
+            Prefix::Lock => prefix_flags |= FLAG_LOCK,
+            // Only the last repeat prefix counts
+            Prefix::Repeat { while_zero } => {
+                prefix_flags &= !(FLAG_REP | FLAG_REPNE);
+                prefix_flags |= if while_zero { FLAG_REP } else { FLAG_REPNE };
+            }
+            Prefix::Segment(segment) => segment_override = Some(segment),
+        }
+
+        byte = reader.next_u8()?;
+        entry = OPCODE_TABLE[byte as usize];
+    }
+
+    let mut mod_rm = None;
+
+    // Group opcodes share a first byte and are told apart by the REG field of the MOD REG R/M byte
+    if let Entry::Group(group) = entry {
+        let byte_2 = reader.next_u8()?;
+        entry = group[((byte_2 >> 3) & 0b111_u8) as usize];
+        mod_rm = Some(byte_2);
+    }
+
+    let (op, layout) = match (entry, mod_rm) {
+        (Entry::Inst(op, layout), _) => (op, layout),
+        (_, Some(mod_rm)) => {
+            return Err(DecodeError::InvalidModRm {
+                offset,
+                byte,
+                mod_rm,
+            })
+        }
+        (_, None) => return Err(DecodeError::UnknownOpcode { offset, byte }),
+    };
+
+    let mod_rm = match (mod_rm, layout.has_mod_rm()) {
+        (Some(mod_rm), _) => mod_rm,
+        (None, true) => reader.next_u8()?,
+        (None, false) => 0,
+    };
+
+    let mut instruction = decode_operands(&mut reader, byte, mod_rm, op, layout)?;
+    instruction.size = reader.len();
+    instruction.flags |= prefix_flags;
+    instruction.segment_override = segment_override;
+
+    Ok(instruction)
+}
+
+/// Reads the operands that follow the opcode (and MOD REG R/M byte, if any) according to `layout`
+fn decode_operands(
+    reader: &mut ByteReader,
+    byte: u8,
+    mod_rm: u8,
+    op: Op,
+    layout: Layout,
+) -> Result<Instruction, DecodeError> {
+    let d_field = (byte >> 1) & 0b1_u8 == 0b1;
+    let w_field = byte & 0b1 == 0b1;
+    let mod_field = (mod_rm >> 6) & 0b11_u8;
+    let reg_field = (mod_rm >> 3) & 0b111_u8;
+
+    let invalid_mod_rm = DecodeError::InvalidModRm {
+        offset: reader.start,
+        byte,
+        mod_rm,
+    };
+
+    let instruction = match layout {
+        Layout::Implied => build(op, [None, None], false),
+
+        Layout::RegRm => {
+            let reg = Operand::Register(decode_register_field(reg_field, w_field));
+            let rm = decode_mod_rm(reader, mod_rm, w_field)?;
+
+            let operands = match d_field {
+                true => [Some(reg), Some(rm)],
+                false => [Some(rm), Some(reg)],
+            };
+
+            build(op, operands, w_field)
+        }
+
+        Layout::LoadAddress => {
+            // Loading an address out of a register makes no sense
+            if mod_field == 0b11 {
+                return Err(invalid_mod_rm);
+            }
+
+            let reg = Operand::Register(decode_register_field(reg_field, true));
+            let rm = decode_mod_rm(reader, mod_rm, true)?;
+
+            build(op, [Some(reg), Some(rm)], true)
+        }
+
+        Layout::RmImmediate { sign_extend } => {
+            // NOTE: Here, MOD determines the addressing mode as usual. W determines the size of
+            // the operation and S tells us whether a single byte of immediate data should be
+            // sign extended to 16 bits
+            let rm = decode_mod_rm(reader, mod_rm, w_field)?;
+            let data = match sign_extend && w_field {
+                true => reader.next_u8()? as i8 as i16 as u16,
+                false => reader.next_data(w_field)?,
+            };
+
+            build(op, [Some(rm), Some(Operand::Immediate(data))], w_field)
+        }
+
+        Layout::Rm => {
+            let rm = decode_mod_rm(reader, mod_rm, w_field)?;
+            build(op, [Some(rm), None], w_field)
+        }
+
+        Layout::RmFar => {
+            // A far pointer is 32 bits, so it can only live in memory
+            if mod_field == 0b11 {
+                return Err(invalid_mod_rm);
+            }
+
+            let rm = decode_mod_rm(reader, mod_rm, true)?;
+
+            let mut instruction = build(op, [Some(rm), None], true);
+            instruction.flags |= FLAG_FAR;
+            instruction
+        }
+
+        Layout::Shift => {
+            let rm = decode_mod_rm(reader, mod_rm, w_field)?;
+            let count = match d_field {
+                true => Operand::Register(Reg::Cl),
+                false => Operand::Immediate(1),
+            };
+
+            build(op, [Some(rm), Some(count)], w_field)
+        }
+
+        Layout::RegImmediate => {
+            let is_wide = (byte >> 3) & 0b1_u8 == 0b1;
+            let reg = Operand::Register(decode_register_field(byte & 0b111, is_wide));
+            let data = reader.next_data(is_wide)?;
+
+            build(op, [Some(reg), Some(Operand::Immediate(data))], is_wide)
+        }
+
+        Layout::Register => {
+            let reg = Operand::Register(decode_register_field(byte & 0b111, true));
+            build(op, [Some(reg), None], true)
+        }
+
+        Layout::AccumulatorRegister => {
+            let reg = Operand::Register(decode_register_field(byte & 0b111, true));
+            build(op, [Some(Operand::Register(Reg::Ax)), Some(reg)], true)
+        }
+
+        Layout::SegmentRegister => {
+            let sreg = Operand::Register(decode_segment_register_field((byte >> 3) & 0b11_u8));
+            build(op, [Some(sreg), None], true)
+        }
+
+        Layout::SegmentRegisterRm => {
+            // Only four segment registers exist, the top bit of the field has to be clear
+            if reg_field & 0b100 != 0 {
+                return Err(invalid_mod_rm);
+            }
+
+            let sreg = Operand::Register(decode_segment_register_field(reg_field));
+            let rm = decode_mod_rm(reader, mod_rm, true)?;
+
+            let operands = match d_field {
+                true => [Some(sreg), Some(rm)],
+                false => [Some(rm), Some(sreg)],
+            };
+
+            build(op, operands, true)
+        }
+
+        Layout::AccumulatorImmediate => {
+            let accumulator = Operand::Register(accumulator_register(w_field));
+            let data = reader.next_data(w_field)?;
+
+            build(
+                op,
+                [Some(accumulator), Some(Operand::Immediate(data))],
+                w_field,
+            )
+        }
+
+        Layout::AccumulatorMemory => {
+            let accumulator = Operand::Register(accumulator_register(w_field));
+            let memory = Operand::Memory(EffectiveAddress {
+                base: AddressBase::Direct,
+                displacement: reader.next_u16()? as i16,
+            });
+
+            let operands = match d_field {
+                true => [Some(memory), Some(accumulator)],
+                false => [Some(accumulator), Some(memory)],
+            };
+
+            build(op, operands, w_field)
+        }
+
+        Layout::PortImmediate | Layout::PortDx => {
+            let accumulator = Operand::Register(accumulator_register(w_field));
+            let port = match layout {
+                Layout::PortImmediate => Operand::Immediate(reader.next_u8()? as u16),
+                _ => Operand::Register(Reg::Dx),
+            };
+
+            let operands = match d_field {
+                true => [Some(port), Some(accumulator)],
+                false => [Some(accumulator), Some(port)],
+            };
+
+            build(op, operands, w_field)
+        }
+
+        Layout::ShortJump => {
+            let displacement = reader.next_u8()? as i8 as i16;
+            build(op, [Some(Operand::Relative(displacement)), None], false)
+        }
+
+        Layout::NearJump => {
+            let displacement = reader.next_u16()? as i16;
+            build(op, [Some(Operand::Relative(displacement)), None], true)
+        }
+
+        Layout::FarPointer => {
+            let offset = reader.next_u16()?;
+            let segment = reader.next_u16()?;
+            build(
+                op,
+                [Some(Operand::FarPointer { segment, offset }), None],
+                true,
+            )
+        }
+
+        Layout::Immediate8 => {
+            let data = reader.next_u8()?;
+
+            // aam/aad carry their base as an immediate, which assemblers only expect to see
+            // written out when it isn't the usual base 10
+            match (op, data) {
+                (Op::Aam | Op::Aad, 10) => build(op, [None, None], false),
+                _ => build(op, [Some(Operand::Immediate(data as u16)), None], false),
+            }
+        }
+
+        Layout::Immediate16 => {
+            let data = reader.next_u16()?;
+            build(op, [Some(Operand::Immediate(data)), None], true)
+        }
+
+        Layout::String => build(op, [None, None], w_field),
+
+        Layout::Escape => {
+            let code = ((byte & 0b111) << 3) | reg_field;
+            let rm = decode_mod_rm(reader, mod_rm, w_field)?;
+            build(op, [Some(Operand::Immediate(code as u16)), Some(rm)], false)
+        }
+    };
+
+    Ok(instruction)
+}
+
+/// Assembles an instruction whose size is filled in once all of its bytes have been read
+fn build(op: Op, operands: [Option<Operand>; 2], is_wide: bool) -> Instruction {
+    Instruction {
+        op,
+        operands,
+        size: 0,
+        flags: if is_wide { FLAG_WIDE } else { 0 },
+        segment_override: None,
+    }
+}
+
+/// Decodes the R/M operand described by a MOD REG R/M byte, reading any displacement bytes
+fn decode_mod_rm(
+    reader: &mut ByteReader,
+    mod_rm: u8,
+    is_wide: bool,
+) -> Result<Operand, DecodeError> {
+    let mod_field = (mod_rm >> 6) & 0b11_u8;
+    let rm_field = mod_rm & 0b111;
+
+    let operand = match mod_field {
+        // Register mode
+        0b11 => Operand::Register(decode_rm_field_at_mod_11(rm_field, is_wide)),
+
+        // Memory mode (no displacement)*, except for direct addressing
+        0b00 if rm_field == 0b110 => Operand::Memory(EffectiveAddress {
+            base: AddressBase::Direct,
+            displacement: reader.next_u16()? as i16,
+        }),
+        0b00 => Operand::Memory(EffectiveAddress {
+            base: decode_rm_field_as_address(rm_field),
+            displacement: 0,
+        }),
+
+        // Memory mode (8bit displacement)
+        0b01 => Operand::Memory(EffectiveAddress {
+            base: decode_rm_field_as_address(rm_field),
+            displacement: reader.next_u8()? as i8 as i16,
+        }),
+
+        // Memory mode (16bit displacement)
+        _ => Operand::Memory(EffectiveAddress {
+            base: decode_rm_field_as_address(rm_field),
+            displacement: reader.next_u16()? as i16,
+        }),
+    };
+
+    Ok(operand)
+}
+
+fn accumulator_register(is_wide: bool) -> Reg {
+    match is_wide {
+        true => Reg::Ax,
+        false => Reg::Al,
+    }
+}
+
+fn decode_rm_field_at_mod_11(rm_field: u8, w_field: bool) -> Reg {
+    // With MOD = 11, R/M names a register using the same encoding as the REG field
+    decode_register_field(rm_field, w_field)
+}
+
+fn decode_rm_field_as_address(rm_field: u8) -> AddressBase {
+    match rm_field {
+        0b000 => AddressBase::BxSi,
+        0b001 => AddressBase::BxDi,
+        0b010 => AddressBase::BpSi,
+        0b011 => AddressBase::BpDi,
+        0b100 => AddressBase::Si,
+        0b101 => AddressBase::Di,
+        0b110 => AddressBase::Bp,
+        _ => AddressBase::Bx,
+    }
+}
+
+fn decode_segment_register_field(sr_field: u8) -> Reg {
+    match sr_field {
+        0b00 => Reg::Es,
+        0b01 => Reg::Cs,
+        0b10 => Reg::Ss,
+        _ => Reg::Ds,
+    }
+}
+
+fn decode_register_field(reg_field: u8, w_field: bool) -> Reg {
+    match w_field {
+        true => match reg_field {
+            0b000 => Reg::Ax,
+            0b001 => Reg::Cx,
+            0b010 => Reg::Dx,
+            0b011 => Reg::Bx,
+            0b100 => Reg::Sp,
+            0b101 => Reg::Bp,
+            0b110 => Reg::Si,
+            _ => Reg::Di,
+        },
+        false => match reg_field {
+            0b000 => Reg::Al,
+            0b001 => Reg::Cl,
+            0b010 => Reg::Dl,
+            0b011 => Reg::Bl,
+            0b100 => Reg::Ah,
+            0b101 => Reg::Ch,
+            0b110 => Reg::Dh,
+            _ => Reg::Bh,
+        },
+    }
+}
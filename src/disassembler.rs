@@ -0,0 +1,343 @@
+//! Whole-program disassembly into assembly that NASM turns back into the same bytes.
+//!
+//! Decoding happens in two passes: the first decodes every instruction front to back, the second
+//! names every branch target that lands on an instruction boundary so jumps can be printed as
+//! `je label_0` instead of a raw displacement.
+
+use crate::decoder::{decode_at, DecodeError};
+use crate::instruction::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// One decoded unit of the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line {
+    Instruction {
+        offset: usize,
+        instruction: Instruction,
+    },
+    /// Bytes that couldn't be decoded. They are kept verbatim so the output still reassembles
+    Undecoded {
+        offset: usize,
+        length: usize,
+        error: DecodeError,
+    },
+}
+
+impl Line {
+    pub fn offset(&self) -> usize {
+        match *self {
+            Line::Instruction { offset, .. } => offset,
+            Line::Undecoded { offset, .. } => offset,
+        }
+    }
+
+    /// Number of bytes of the program covered by the line
+    pub fn size(&self) -> usize {
+        match *self {
+            Line::Instruction { instruction, .. } => instruction.size,
+            Line::Undecoded { length, .. } => length,
+        }
+    }
+}
+
+/// Decodes all of `bytes`. The first undecodable instruction is returned as an error unless
+/// `continue_on_error` is set, in which case the offending byte becomes a [`Line::Undecoded`]
+/// and decoding resumes on the next one. A truncated instruction at the end swallows the rest of
+/// the buffer.
+pub fn decode_all(bytes: &[u8], continue_on_error: bool) -> Result<Vec<Line>, DecodeError> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let line = match decode_at(bytes, offset) {
+            Ok(instruction) => {
+                log::debug!(
+                    "{:04X}: {} ({} bytes)",
+                    offset,
+                    instruction,
+                    instruction.size
+                );
+
+                Line::Instruction {
+                    offset,
+                    instruction,
+                }
+            }
+            Err(error) if continue_on_error => {
+                log::debug!("{:04X}: skipping after {}", offset, error);
+
+                let length = match error {
+                    DecodeError::TruncatedInstruction { .. } => bytes.len() - offset,
+                    _ => 1,
+                };
+
+                Line::Undecoded {
+                    offset,
+                    length,
+                    error,
+                }
+            }
+            Err(error) => return Err(error),
+        };
+
+        offset += line.size();
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+/// Offset a relative jump lands on, or `None` if it isn't a relative jump
+pub fn jump_target(offset: usize, instruction: &Instruction) -> Option<usize> {
+    match instruction.operands {
+        [Some(Operand::Relative(displacement)), None] => {
+            let next = offset as isize + instruction.size as isize;
+            usize::try_from(next + displacement as isize).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Names for every jump target that a label can be put in front of, numbered in address order
+pub fn collect_labels(lines: &[Line]) -> BTreeMap<usize, String> {
+    // Undecoded bytes are printed one per line, so any of them can carry a label
+    let boundaries: Vec<usize> = lines
+        .iter()
+        .flat_map(|line| match *line {
+            Line::Instruction { offset, .. } => offset..offset + 1,
+            Line::Undecoded { offset, length, .. } => offset..offset + length,
+        })
+        .collect();
+
+    let mut targets: Vec<usize> = lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Instruction {
+                offset,
+                instruction,
+            } => jump_target(*offset, instruction),
+            Line::Undecoded { .. } => None,
+        })
+        .filter(|target| boundaries.binary_search(target).is_ok())
+        .collect();
+
+    targets.sort_unstable();
+    targets.dedup();
+
+    targets
+        .into_iter()
+        .enumerate()
+        .map(|(index, target)| (target, format!("label_{}", index)))
+        .collect()
+}
+
+/// Prints `lines` as NASM source, one instruction per line, with labels in front of jump targets
+pub fn to_assembly(bytes: &[u8], lines: &[Line]) -> String {
+    let labels = collect_labels(lines);
+    let mut assembly = String::new();
+
+    for line in lines {
+        match *line {
+            Line::Instruction {
+                offset,
+                instruction,
+            } => {
+                if let Some(label) = labels.get(&offset) {
+                    writeln!(assembly, "{}:", label).unwrap();
+                }
+
+                writeln!(
+                    assembly,
+                    "{}",
+                    format_instruction(offset, &instruction, &labels)
+                )
+                .unwrap();
+            }
+            Line::Undecoded { offset, length, .. } => {
+                for (offset, byte) in bytes.iter().enumerate().skip(offset).take(length) {
+                    if let Some(label) = labels.get(&offset) {
+                        writeln!(assembly, "{}:", label).unwrap();
+                    }
+
+                    writeln!(assembly, "db 0x{:02X}", byte).unwrap();
+                }
+            }
+        }
+    }
+
+    assembly
+}
+
+/// Width of the raw bytes column in a listing, enough for the longest instruction without prefixes
+const LISTING_BYTES_WIDTH: usize = 17;
+
+/// Prints `lines` as an annotated hex listing, where every instruction is preceded by its offset
+/// and the bytes it was decoded from, e.g. `0000: 89 D9             mov cx, bx`. A summary of the
+/// bytes that couldn't be decoded closes the listing.
+pub fn to_listing(bytes: &[u8], lines: &[Line]) -> String {
+    let labels = collect_labels(lines);
+    let mut listing = String::new();
+
+    for line in lines {
+        let offset = line.offset();
+        let line_bytes = &bytes[offset..offset + line.size()];
+        let raw_bytes: Vec<String> = line_bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+
+        if let Some(label) = labels.get(&offset) {
+            writeln!(listing, "{}:", label).unwrap();
+        }
+
+        let text = match *line {
+            Line::Instruction { instruction, .. } => {
+                format_instruction(offset, &instruction, &labels)
+            }
+            Line::Undecoded { .. } => {
+                let data: Vec<String> = line_bytes
+                    .iter()
+                    .map(|byte| format!("0x{:02X}", byte))
+                    .collect();
+                format!("db {}", data.join(", "))
+            }
+        };
+
+        writeln!(
+            listing,
+            "{:04X}: {:<width$} {}",
+            offset,
+            raw_bytes.join(" "),
+            text,
+            width = LISTING_BYTES_WIDTH
+        )
+        .unwrap();
+    }
+
+    let ranges = undecoded_ranges(lines);
+    let undecoded: usize = ranges.iter().map(|(range, _)| range.len()).sum();
+
+    writeln!(listing).unwrap();
+    if ranges.is_empty() {
+        writeln!(listing, "; All {} bytes decoded", bytes.len()).unwrap();
+    } else {
+        let plural = if ranges.len() == 1 { "" } else { "s" };
+        writeln!(
+            listing,
+            "; {} of {} bytes undecoded in {} range{}:",
+            undecoded,
+            bytes.len(),
+            ranges.len(),
+            plural
+        )
+        .unwrap();
+
+        for (range, error) in ranges {
+            let plural = if range.len() == 1 { "" } else { "s" };
+            writeln!(
+                listing,
+                ";   {:04X}..{:04X} ({} byte{}): {}",
+                range.start,
+                range.end,
+                range.len(),
+                plural,
+                error
+            )
+            .unwrap();
+        }
+    }
+
+    listing
+}
+
+/// Runs of consecutive undecoded bytes, each with the error that started it. Neighbouring bytes
+/// only share a run when they failed for the same reason
+pub fn undecoded_ranges(lines: &[Line]) -> Vec<(Range<usize>, DecodeError)> {
+    let mut ranges: Vec<(Range<usize>, DecodeError)> = Vec::new();
+
+    for line in lines {
+        let Line::Undecoded {
+            offset,
+            length,
+            error,
+        } = *line
+        else {
+            continue;
+        };
+
+        match ranges.last_mut() {
+            Some((range, first)) if range.end == offset && same_failure(first, &error) => {
+                range.end += length
+            }
+            _ => ranges.push((offset..offset + length, error)),
+        }
+    }
+
+    ranges
+}
+
+/// Whether two decode errors are the same apart from where they happened
+fn same_failure(a: &DecodeError, b: &DecodeError) -> bool {
+    match (*a, *b) {
+        (
+            DecodeError::TruncatedInstruction { needed: a, .. },
+            DecodeError::TruncatedInstruction { needed: b, .. },
+        ) => a == b,
+        (
+            DecodeError::UnknownOpcode { byte: a, .. },
+            DecodeError::UnknownOpcode { byte: b, .. },
+        ) => a == b,
+        (
+            DecodeError::InvalidModRm {
+                byte: a,
+                mod_rm: a_mod_rm,
+                ..
+            },
+            DecodeError::InvalidModRm {
+                byte: b,
+                mod_rm: b_mod_rm,
+                ..
+            },
+        ) => (a, a_mod_rm) == (b, b_mod_rm),
+        _ => false,
+    }
+}
+
+/// Prints an instruction with its jump target replaced by a label, see [`jump_operand`]
+pub fn format_instruction(
+    offset: usize,
+    instruction: &Instruction,
+    labels: &BTreeMap<usize, String>,
+) -> String {
+    match jump_operand(offset, instruction, labels) {
+        Some(target) => format!("{} {}", instruction.op.mnemonic(), target),
+        None => instruction.to_string(),
+    }
+}
+
+/// The operand of a relative jump, call or loop as a label. Targets without a label are written
+/// relative to the start of the instruction (`$+5`), which NASM understands too. `None` for
+/// anything that doesn't take a relative target
+pub fn jump_operand(
+    offset: usize,
+    instruction: &Instruction,
+    labels: &BTreeMap<usize, String>,
+) -> Option<String> {
+    let Some(Operand::Relative(displacement)) = instruction.operands[0] else {
+        return None;
+    };
+
+    let target = match jump_target(offset, instruction).and_then(|target| labels.get(&target)) {
+        Some(label) => label.clone(),
+        None => format!("${:+}", instruction.size as i32 + displacement as i32),
+    };
+
+    // NASM picks the short form of jmp whenever the target is close enough, so keep the near
+    // form if that's what the original used
+    Some(match (instruction.op, instruction.is_wide()) {
+        (Op::Jmp, true) => format!("near {}", target),
+        _ => target,
+    })
+}
@@ -0,0 +1,533 @@
+use std::fmt;
+
+/// Instruction operates on 16-bit (word) operands instead of 8-bit (byte) operands
+pub const FLAG_WIDE: u8 = 0b0000_0001;
+/// Instruction was preceded by a LOCK prefix
+pub const FLAG_LOCK: u8 = 0b0000_0010;
+/// Indirect call/jmp through a 32-bit segment:offset pointer in memory
+pub const FLAG_FAR: u8 = 0b0000_0100;
+/// String instruction preceded by REP/REPE/REPZ (0xF3), repeating while CX isn't 0 (and ZF is set
+/// for cmps/scas)
+pub const FLAG_REP: u8 = 0b0000_1000;
+/// String instruction preceded by REPNE/REPNZ (0xF2), repeating while CX isn't 0 (and ZF is clear
+/// for cmps/scas)
+pub const FLAG_REPNE: u8 = 0b0001_0000;
+
+/// A single decoded 8086 instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub op: Op,
+    /// Destination first, source second. Unused slots are `None`
+    pub operands: [Option<Operand>; 2],
+    /// Encoded length of the instruction in bytes, including prefixes
+    pub size: usize,
+    pub flags: u8,
+    /// Segment register named by a segment override prefix, replacing the default segment of the
+    /// memory operand
+    pub segment_override: Option<Reg>,
+}
+
+impl Instruction {
+    pub fn is_wide(&self) -> bool {
+        self.flags & FLAG_WIDE != 0
+    }
+
+    pub fn is_far(&self) -> bool {
+        self.flags & FLAG_FAR != 0
+    }
+
+    /// Whether a REP prefix of either kind repeats the instruction
+    pub fn is_repeated(&self) -> bool {
+        self.flags & (FLAG_REP | FLAG_REPNE) != 0
+    }
+
+    /// The mnemonic as printed, with the prefix words in front of it and the size suffix of string
+    /// instructions on it: `lock inc`, `rep movsb`, `call far`
+    pub fn full_mnemonic(&self) -> String {
+        let mut mnemonic = String::new();
+
+        if self.flags & FLAG_LOCK != 0 {
+            mnemonic.push_str("lock ");
+        }
+
+        // cmps and scas stop repeating on a mismatch, which the E in REPE spells out
+        let compares = matches!(self.op, Op::Cmps | Op::Scas);
+        if self.flags & FLAG_REP != 0 {
+            mnemonic.push_str(if compares { "repe " } else { "rep " });
+        }
+        if self.flags & FLAG_REPNE != 0 {
+            mnemonic.push_str("repne ");
+        }
+
+        // With no memory operand to carry it, an override is written as a prefix, like NASM's
+        // `es lodsb`. On a string instruction it applies to the DS:SI side, anywhere else it does
+        // nothing but still takes up a byte
+        let has_memory_operand = self
+            .operands
+            .iter()
+            .any(|operand| matches!(operand, Some(Operand::Memory(_))));
+        if let (false, Some(segment)) = (has_memory_operand, self.segment_override) {
+            mnemonic.push_str(&format!("{} ", segment));
+        }
+
+        mnemonic.push_str(self.op.mnemonic());
+
+        if self.op.is_string() {
+            mnemonic.push_str(if self.is_wide() { "w" } else { "b" });
+        }
+
+        if self.is_far() {
+            mnemonic.push_str(" far");
+        }
+
+        mnemonic
+    }
+
+    /// The operands as printed, destination first
+    pub fn formatted_operands(&self) -> Vec<String> {
+        let size = if self.is_wide() { "word" } else { "byte" };
+
+        // A register operand implies the operation size. Without one, the size has to be spelled
+        // out on the memory operand, except for shift counts which never say anything about the
+        // size
+        let sized_by_register = match self.operands {
+            [Some(Operand::Register(_)), _] => true,
+            [_, Some(Operand::Register(_))] => !self.op.is_shift(),
+            _ => false,
+        };
+        let needs_size = !sized_by_register && !self.is_far() && self.op != Op::Esc;
+
+        self.operands
+            .iter()
+            .flatten()
+            .map(|operand| match operand {
+                Operand::Register(reg) => reg.to_string(),
+                Operand::Memory(address) => {
+                    let mut text = String::new();
+                    if needs_size {
+                        text.push_str(&format!("{} ", size));
+                    }
+
+                    address.write(&mut text, self.segment_override).unwrap();
+                    text
+                }
+                Operand::Immediate(value) => {
+                    if self.op.has_unsigned_immediate() || self.op.is_shift() {
+                        value.to_string()
+                    } else if self.is_wide() {
+                        (*value as i16).to_string()
+                    } else {
+                        (*value as u8 as i8).to_string()
+                    }
+                }
+                Operand::Relative(displacement) => displacement.to_string(),
+                Operand::FarPointer { segment, offset } => format!("{}:{}", segment, offset),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    // Data transfer
+    Mov,
+    Push,
+    Pop,
+    Xchg,
+    In,
+    Out,
+    Xlat,
+    Lea,
+    Lds,
+    Les,
+    Lahf,
+    Sahf,
+    Pushf,
+    Popf,
+
+    // Arithmetic
+    Add,
+    Adc,
+    Inc,
+    Aaa,
+    Daa,
+    Sub,
+    Sbb,
+    Dec,
+    Neg,
+    Cmp,
+    Aas,
+    Das,
+    Mul,
+    Imul,
+    Aam,
+    Div,
+    Idiv,
+    Aad,
+    Cbw,
+    Cwd,
+
+    // Logic
+    Not,
+    Shl,
+    Shr,
+    Sar,
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+    And,
+    Test,
+    Or,
+    Xor,
+
+    // String manipulation
+    Movs,
+    Cmps,
+    Scas,
+    Lods,
+    Stos,
+
+    // Control transfer
+    Call,
+    Jmp,
+    Ret,
+    Retf,
+    Jo,
+    Jno,
+    Jb,
+    Jnb,
+    Je,
+    Jne,
+    Jbe,
+    Ja,
+    Js,
+    Jns,
+    Jp,
+    Jnp,
+    Jl,
+    Jnl,
+    Jle,
+    Jg,
+    Loopnz,
+    Loopz,
+    Loop,
+    Jcxz,
+    Int,
+    Int3,
+    Into,
+    Iret,
+
+    // Processor control
+    Clc,
+    Cmc,
+    Stc,
+    Cld,
+    Std,
+    Cli,
+    Sti,
+    Hlt,
+    Wait,
+    Esc,
+    Nop,
+}
+
+impl Op {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Op::Mov => "mov",
+            Op::Push => "push",
+            Op::Pop => "pop",
+            Op::Xchg => "xchg",
+            Op::In => "in",
+            Op::Out => "out",
+            Op::Xlat => "xlat",
+            Op::Lea => "lea",
+            Op::Lds => "lds",
+            Op::Les => "les",
+            Op::Lahf => "lahf",
+            Op::Sahf => "sahf",
+            Op::Pushf => "pushf",
+            Op::Popf => "popf",
+
+            Op::Add => "add",
+            Op::Adc => "adc",
+            Op::Inc => "inc",
+            Op::Aaa => "aaa",
+            Op::Daa => "daa",
+            Op::Sub => "sub",
+            Op::Sbb => "sbb",
+            Op::Dec => "dec",
+            Op::Neg => "neg",
+            Op::Cmp => "cmp",
+            Op::Aas => "aas",
+            Op::Das => "das",
+            Op::Mul => "mul",
+            Op::Imul => "imul",
+            Op::Aam => "aam",
+            Op::Div => "div",
+            Op::Idiv => "idiv",
+            Op::Aad => "aad",
+            Op::Cbw => "cbw",
+            Op::Cwd => "cwd",
+
+            Op::Not => "not",
+            Op::Shl => "shl",
+            Op::Shr => "shr",
+            Op::Sar => "sar",
+            Op::Rol => "rol",
+            Op::Ror => "ror",
+            Op::Rcl => "rcl",
+            Op::Rcr => "rcr",
+            Op::And => "and",
+            Op::Test => "test",
+            Op::Or => "or",
+            Op::Xor => "xor",
+
+            Op::Movs => "movs",
+            Op::Cmps => "cmps",
+            Op::Scas => "scas",
+            Op::Lods => "lods",
+            Op::Stos => "stos",
+
+            Op::Call => "call",
+            Op::Jmp => "jmp",
+            Op::Ret => "ret",
+            Op::Retf => "retf",
+            Op::Jo => "jo",
+            Op::Jno => "jno",
+            Op::Jb => "jb",
+            Op::Jnb => "jnb",
+            Op::Je => "je",
+            Op::Jne => "jne",
+            Op::Jbe => "jbe",
+            Op::Ja => "ja",
+            Op::Js => "js",
+            Op::Jns => "jns",
+            Op::Jp => "jp",
+            Op::Jnp => "jnp",
+            Op::Jl => "jl",
+            Op::Jnl => "jnl",
+            Op::Jle => "jle",
+            Op::Jg => "jg",
+            Op::Loopnz => "loopnz",
+            Op::Loopz => "loopz",
+            Op::Loop => "loop",
+            Op::Jcxz => "jcxz",
+            Op::Int => "int",
+            Op::Int3 => "int3",
+            Op::Into => "into",
+            Op::Iret => "iret",
+
+            Op::Clc => "clc",
+            Op::Cmc => "cmc",
+            Op::Stc => "stc",
+            Op::Cld => "cld",
+            Op::Std => "std",
+            Op::Cli => "cli",
+            Op::Sti => "sti",
+            Op::Hlt => "hlt",
+            Op::Wait => "wait",
+            Op::Esc => "esc",
+            Op::Nop => "nop",
+        }
+    }
+
+    /// Shifts and rotates, whose second operand is a count rather than a value of the same size
+    pub fn is_shift(&self) -> bool {
+        matches!(
+            self,
+            Op::Shl | Op::Shr | Op::Sar | Op::Rol | Op::Ror | Op::Rcl | Op::Rcr
+        )
+    }
+
+    /// movs/cmps/scas/lods/stos, which work on DS:SI and ES:DI and can be repeated with REP. They
+    /// are written with a `b` or `w` suffix for the operation size
+    pub fn is_string(&self) -> bool {
+        matches!(self, Op::Movs | Op::Cmps | Op::Scas | Op::Lods | Op::Stos)
+    }
+
+    /// Instructions whose immediate is a port, vector or count and so reads better unsigned
+    fn has_unsigned_immediate(&self) -> bool {
+        matches!(
+            self,
+            Op::In | Op::Out | Op::Int | Op::Ret | Op::Retf | Op::Aam | Op::Aad | Op::Esc
+        )
+    }
+}
+
+/// Every register an instruction can name. The 8-bit registers alias the low and high halves of
+/// AX, BX, CX and DX
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reg {
+    Al,
+    Cl,
+    Dl,
+    Bl,
+    Ah,
+    Ch,
+    Dh,
+    Bh,
+    Ax,
+    Cx,
+    Dx,
+    Bx,
+    Sp,
+    Bp,
+    Si,
+    Di,
+    Es,
+    Cs,
+    Ss,
+    Ds,
+}
+
+impl Reg {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reg::Al => "al",
+            Reg::Cl => "cl",
+            Reg::Dl => "dl",
+            Reg::Bl => "bl",
+            Reg::Ah => "ah",
+            Reg::Ch => "ch",
+            Reg::Dh => "dh",
+            Reg::Bh => "bh",
+            Reg::Ax => "ax",
+            Reg::Cx => "cx",
+            Reg::Dx => "dx",
+            Reg::Bx => "bx",
+            Reg::Sp => "sp",
+            Reg::Bp => "bp",
+            Reg::Si => "si",
+            Reg::Di => "di",
+            Reg::Es => "es",
+            Reg::Cs => "cs",
+            Reg::Ss => "ss",
+            Reg::Ds => "ds",
+        }
+    }
+
+    pub fn is_segment(&self) -> bool {
+        matches!(self, Reg::Es | Reg::Cs | Reg::Ss | Reg::Ds)
+    }
+
+    /// Whether the register holds 16 bits rather than 8
+    pub fn is_wide(&self) -> bool {
+        !matches!(
+            self,
+            Reg::Al | Reg::Cl | Reg::Dl | Reg::Bl | Reg::Ah | Reg::Ch | Reg::Dh | Reg::Bh
+        )
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Reg),
+    Memory(EffectiveAddress),
+    Immediate(u16),
+    /// Jump displacement relative to the end of the instruction
+    Relative(i16),
+    /// Absolute segment:offset target of a direct far call/jmp
+    FarPointer {
+        segment: u16,
+        offset: u16,
+    },
+}
+
+/// The register combination used to form a memory address, as selected by the R/M field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressBase {
+    BxSi,
+    BxDi,
+    BpSi,
+    BpDi,
+    Si,
+    Di,
+    Bp,
+    Bx,
+    /// No registers involved, the displacement is the full 16-bit address
+    Direct,
+}
+
+impl AddressBase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AddressBase::BxSi => "bx + si",
+            AddressBase::BxDi => "bx + di",
+            AddressBase::BpSi => "bp + si",
+            AddressBase::BpDi => "bp + di",
+            AddressBase::Si => "si",
+            AddressBase::Di => "di",
+            AddressBase::Bp => "bp",
+            AddressBase::Bx => "bx",
+            AddressBase::Direct => "",
+        }
+    }
+
+    /// Segment used when there's no override prefix. Anything involving BP is assumed to be
+    /// addressing the stack
+    pub fn default_segment(&self) -> Reg {
+        match self {
+            AddressBase::BpSi | AddressBase::BpDi | AddressBase::Bp => Reg::Ss,
+            _ => Reg::Ds,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffectiveAddress {
+    pub base: AddressBase,
+    pub displacement: i16,
+}
+
+impl EffectiveAddress {
+    /// Writes the address the way NASM expects it, with the segment override inside the brackets:
+    /// `[es:bx + si - 32]`
+    fn write(&self, f: &mut impl fmt::Write, segment: Option<Reg>) -> fmt::Result {
+        write!(f, "[")?;
+
+        if let Some(segment) = segment {
+            write!(f, "{}:", segment)?;
+        }
+
+        match self.base {
+            AddressBase::Direct => write!(f, "{}", self.displacement as u16)?,
+            base if self.displacement == 0 => write!(f, "{}", base.as_str())?,
+            base if self.displacement.is_negative() => write!(
+                f,
+                "{} - {}",
+                base.as_str(),
+                self.displacement.unsigned_abs()
+            )?,
+            base => write!(f, "{} + {}", base.as_str(), self.displacement)?,
+        }
+
+        write!(f, "]")
+    }
+}
+
+impl fmt::Display for EffectiveAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.full_mnemonic())?;
+
+        let operands = self.formatted_operands();
+        if !operands.is_empty() {
+            write!(f, " {}", operands.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,23 @@
+//! Instruction decoder and simulator for the intel 8086 CPU.
+//!
+//! [`decode`] turns raw machine code into structured [`Instruction`] values, which can be printed
+//! back out as assembly through their `Display` impl or run against a [`CpuState`] with
+//! [`simulator::execute`].
+
+mod alu;
+pub mod assembler;
+pub mod cpu_state;
+pub mod cycles;
+pub mod debugger;
+pub mod decoder;
+pub mod disassembler;
+pub mod instruction;
+pub mod memory;
+mod opcode_table;
+pub mod report;
+pub mod simulator;
+
+pub use cpu_state::{CpuState, Flag};
+pub use decoder::{decode, decode_at, DecodeError};
+pub use instruction::*;
+pub use memory::Memory;
@@ -0,0 +1,85 @@
+use std::fmt;
+
+/// The 8086 has a 20-bit address bus
+pub const MEMORY_SIZE: usize = 1 << 20;
+
+/// The full 1 MiB address space of the 8086. Addresses wrap around at the top of memory just like
+/// they do on the real chip, and words are stored little-endian.
+pub struct Memory {
+    bytes: Box<[u8]>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory {
+            bytes: vec![0; MEMORY_SIZE].into_boxed_slice(),
+        }
+    }
+
+    pub fn read_u8(&self, address: u32) -> u8 {
+        self.bytes[wrap(address)]
+    }
+
+    pub fn write_u8(&mut self, address: u32, value: u8) {
+        self.bytes[wrap(address)] = value;
+    }
+
+    /// Reads the word at `address`, low byte first
+    pub fn read_u16(&self, address: u32) -> u16 {
+        u16::from_le_bytes([self.read_u8(address), self.read_u8(address.wrapping_add(1))])
+    }
+
+    /// Writes `value` at `address`, low byte first
+    pub fn write_u16(&mut self, address: u32, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write_u8(address, low);
+        self.write_u8(address.wrapping_add(1), high);
+    }
+
+    /// Reads a byte or a word depending on `is_wide`. Bytes are zero extended
+    pub fn read(&self, address: u32, is_wide: bool) -> u16 {
+        match is_wide {
+            true => self.read_u16(address),
+            false => self.read_u8(address) as u16,
+        }
+    }
+
+    /// Writes a byte or a word depending on `is_wide`. Only the low 8 bits are written for bytes
+    pub fn write(&mut self, address: u32, value: u16, is_wide: bool) {
+        match is_wide {
+            true => self.write_u16(address, value),
+            false => self.write_u8(address, value as u8),
+        }
+    }
+
+    /// Copies `data` into memory starting at `address`
+    pub fn load(&mut self, address: u32, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.write_u8(address.wrapping_add(i as u32), *byte);
+        }
+    }
+
+    /// The raw contents of the whole address space
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Printing a megabyte of zeroes isn't useful to anyone
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Memory")
+            .field("size", &self.bytes.len())
+            .finish()
+    }
+}
+
+fn wrap(address: u32) -> usize {
+    address as usize % MEMORY_SIZE
+}
@@ -0,0 +1,355 @@
+//! Table describing how every possible first byte of an instruction is decoded.
+//!
+//! Each of the 256 opcodes maps to an [`Entry`] naming the operation and the [`Layout`] of the
+//! bytes that follow it. Opcodes whose operation depends on the REG field of the MOD REG R/M byte
+//! point at an 8-entry group table instead.
+
+use crate::instruction::{Op, Reg};
+
+/// Shape of the bytes following the opcode, and where the operands come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Layout {
+    /// No operands
+    Implied,
+    /// `op D W | MOD REG R/M | disp-lo | disp-hi`
+    RegRm,
+    /// `op | MOD REG R/M | disp-lo | disp-hi` with a word register destination and an R/M operand
+    /// that has to be memory (lea/lds/les)
+    LoadAddress,
+    /// `op S W | MOD op R/M | disp-lo | disp-hi | data | data if W`, where `sign_extend` means a
+    /// single byte of data is sign extended to a word
+    RmImmediate { sign_extend: bool },
+    /// `op W | MOD op R/M | disp-lo | disp-hi` with the R/M operand as the only operand
+    Rm,
+    /// Like [`Layout::Rm`], but the operand has to be a 32-bit pointer in memory
+    RmFar,
+    /// `op V W | MOD op R/M | disp-lo | disp-hi`, shifting by 1 or by CL depending on V
+    Shift,
+    /// `op W REG | data | data if W`
+    RegImmediate,
+    /// `op REG` naming a word register
+    Register,
+    /// `op REG` exchanging a word register with AX
+    AccumulatorRegister,
+    /// `op SR op` naming a segment register
+    SegmentRegister,
+    /// `op D | MOD 0 SR R/M | disp-lo | disp-hi` moving between a segment register and a word R/M
+    /// operand
+    SegmentRegisterRm,
+    /// `op W | data | data if W` with AL/AX as the destination
+    AccumulatorImmediate,
+    /// `op D W | addr-lo | addr-hi` moving between AL/AX and a direct address
+    AccumulatorMemory,
+    /// `op D W | data-8` transferring between AL/AX and a fixed port
+    PortImmediate,
+    /// `op D W` transferring between AL/AX and the port in DX
+    PortDx,
+    /// `op | IP-INC8`
+    ShortJump,
+    /// `op | IP-INC-lo | IP-INC-hi`
+    NearJump,
+    /// `op | IP-lo | IP-hi | CS-lo | CS-hi`
+    FarPointer,
+    /// `op | data-8`
+    Immediate8,
+    /// `op | data-lo | data-hi`
+    Immediate16,
+    /// `op W` string instruction working on DS:SI and/or ES:DI
+    String,
+    /// `op xxx | MOD yyy R/M | disp-lo | disp-hi` handing xxxyyy and the operand to a coprocessor
+    Escape,
+}
+
+impl Layout {
+    /// Whether the opcode is followed by a MOD REG R/M byte
+    pub(crate) fn has_mod_rm(&self) -> bool {
+        matches!(
+            self,
+            Layout::RegRm
+                | Layout::LoadAddress
+                | Layout::RmImmediate { .. }
+                | Layout::Rm
+                | Layout::RmFar
+                | Layout::Shift
+                | Layout::SegmentRegisterRm
+                | Layout::Escape
+        )
+    }
+}
+
+/// Bytes that modify the instruction following them rather than being instructions themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Prefix {
+    Lock,
+    /// REP/REPE/REPZ (0xF3) or REPNE/REPNZ (0xF2) in front of a string instruction. `while_zero`
+    /// is what cmps/scas need ZF to be to keep going
+    Repeat {
+        while_zero: bool,
+    },
+    /// Use the named segment register instead of the default one for the memory operand
+    Segment(Reg),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Entry {
+    Invalid,
+    Inst(Op, Layout),
+    /// The operation is picked by the REG field of the following MOD REG R/M byte
+    Group(&'static [Entry; 8]),
+    Prefix(Prefix),
+}
+
+use Entry::*;
+use Layout::*;
+
+/// 0x80-0x83: immediate to register/memory arithmetic
+const fn arithmetic_group(sign_extend: bool) -> [Entry; 8] {
+    let layout = RmImmediate { sign_extend };
+    [
+        Inst(Op::Add, layout),
+        Inst(Op::Or, layout),
+        Inst(Op::Adc, layout),
+        Inst(Op::Sbb, layout),
+        Inst(Op::And, layout),
+        Inst(Op::Sub, layout),
+        Inst(Op::Xor, layout),
+        Inst(Op::Cmp, layout),
+    ]
+}
+
+static GROUP_80: [Entry; 8] = arithmetic_group(false);
+static GROUP_83: [Entry; 8] = arithmetic_group(true);
+
+/// 0x8F: pop register/memory, only valid with a REG field of 0
+static GROUP_8F: [Entry; 8] = [
+    Inst(Op::Pop, Rm),
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+];
+
+/// 0xC6/0xC7: immediate to register/memory mov, only valid with a REG field of 0
+static GROUP_C6: [Entry; 8] = [
+    Inst(Op::Mov, RmImmediate { sign_extend: false }),
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+];
+
+/// 0xD0-0xD3: shifts and rotates by 1 or by CL
+static GROUP_D0: [Entry; 8] = [
+    Inst(Op::Rol, Shift),
+    Inst(Op::Ror, Shift),
+    Inst(Op::Rcl, Shift),
+    Inst(Op::Rcr, Shift),
+    Inst(Op::Shl, Shift),
+    Inst(Op::Shr, Shift),
+    Invalid,
+    Inst(Op::Sar, Shift),
+];
+
+/// 0xF6/0xF7: test immediate and the unary arithmetic instructions
+static GROUP_F6: [Entry; 8] = [
+    Inst(Op::Test, RmImmediate { sign_extend: false }),
+    Invalid,
+    Inst(Op::Not, Rm),
+    Inst(Op::Neg, Rm),
+    Inst(Op::Mul, Rm),
+    Inst(Op::Imul, Rm),
+    Inst(Op::Div, Rm),
+    Inst(Op::Idiv, Rm),
+];
+
+/// 0xFE: byte increment/decrement
+static GROUP_FE: [Entry; 8] = [
+    Inst(Op::Inc, Rm),
+    Inst(Op::Dec, Rm),
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+    Invalid,
+];
+
+/// 0xFF: word increment/decrement, indirect call/jmp and push
+static GROUP_FF: [Entry; 8] = [
+    Inst(Op::Inc, Rm),
+    Inst(Op::Dec, Rm),
+    Inst(Op::Call, Rm),
+    Inst(Op::Call, RmFar),
+    Inst(Op::Jmp, Rm),
+    Inst(Op::Jmp, RmFar),
+    Inst(Op::Push, Rm),
+    Invalid,
+];
+
+pub(crate) static OPCODE_TABLE: [Entry; 256] = build_opcode_table();
+
+/// Fills `table[first..=last]` with the same entry
+const fn fill(table: &mut [Entry; 256], first: usize, last: usize, entry: Entry) {
+    let mut opcode = first;
+    while opcode <= last {
+        table[opcode] = entry;
+        opcode += 1;
+    }
+}
+
+const fn build_opcode_table() -> [Entry; 256] {
+    let mut table = [Invalid; 256];
+
+    // The eight arithmetic/logic operations share one layout, eight opcodes apart:
+    // 0-3 register/memory with register, 4-5 immediate to accumulator
+    let arithmetic = [
+        Op::Add,
+        Op::Or,
+        Op::Adc,
+        Op::Sbb,
+        Op::And,
+        Op::Sub,
+        Op::Xor,
+        Op::Cmp,
+    ];
+    let mut index = 0;
+    while index < arithmetic.len() {
+        let base = index * 8;
+        fill(&mut table, base, base + 3, Inst(arithmetic[index], RegRm));
+        fill(
+            &mut table,
+            base + 4,
+            base + 5,
+            Inst(arithmetic[index], AccumulatorImmediate),
+        );
+        index += 1;
+    }
+
+    table[0x06] = Inst(Op::Push, SegmentRegister);
+    table[0x07] = Inst(Op::Pop, SegmentRegister);
+    table[0x0E] = Inst(Op::Push, SegmentRegister);
+    table[0x16] = Inst(Op::Push, SegmentRegister);
+    table[0x17] = Inst(Op::Pop, SegmentRegister);
+    table[0x1E] = Inst(Op::Push, SegmentRegister);
+    table[0x1F] = Inst(Op::Pop, SegmentRegister);
+
+    table[0x26] = Entry::Prefix(Prefix::Segment(Reg::Es));
+    table[0x2E] = Entry::Prefix(Prefix::Segment(Reg::Cs));
+    table[0x36] = Entry::Prefix(Prefix::Segment(Reg::Ss));
+    table[0x3E] = Entry::Prefix(Prefix::Segment(Reg::Ds));
+
+    table[0x27] = Inst(Op::Daa, Implied);
+    table[0x2F] = Inst(Op::Das, Implied);
+    table[0x37] = Inst(Op::Aaa, Implied);
+    table[0x3F] = Inst(Op::Aas, Implied);
+
+    fill(&mut table, 0x40, 0x47, Inst(Op::Inc, Register));
+    fill(&mut table, 0x48, 0x4F, Inst(Op::Dec, Register));
+    fill(&mut table, 0x50, 0x57, Inst(Op::Push, Register));
+    fill(&mut table, 0x58, 0x5F, Inst(Op::Pop, Register));
+
+    table[0x70] = Inst(Op::Jo, ShortJump);
+    table[0x71] = Inst(Op::Jno, ShortJump);
+    table[0x72] = Inst(Op::Jb, ShortJump);
+    table[0x73] = Inst(Op::Jnb, ShortJump);
+    table[0x74] = Inst(Op::Je, ShortJump);
+    table[0x75] = Inst(Op::Jne, ShortJump);
+    table[0x76] = Inst(Op::Jbe, ShortJump);
+    table[0x77] = Inst(Op::Ja, ShortJump);
+    table[0x78] = Inst(Op::Js, ShortJump);
+    table[0x79] = Inst(Op::Jns, ShortJump);
+    table[0x7A] = Inst(Op::Jp, ShortJump);
+    table[0x7B] = Inst(Op::Jnp, ShortJump);
+    table[0x7C] = Inst(Op::Jl, ShortJump);
+    table[0x7D] = Inst(Op::Jnl, ShortJump);
+    table[0x7E] = Inst(Op::Jle, ShortJump);
+    table[0x7F] = Inst(Op::Jg, ShortJump);
+
+    table[0x80] = Group(&GROUP_80);
+    table[0x81] = Group(&GROUP_80);
+    table[0x82] = Group(&GROUP_80);
+    table[0x83] = Group(&GROUP_83);
+    fill(&mut table, 0x84, 0x85, Inst(Op::Test, RegRm));
+    fill(&mut table, 0x86, 0x87, Inst(Op::Xchg, RegRm));
+    fill(&mut table, 0x88, 0x8B, Inst(Op::Mov, RegRm));
+    table[0x8C] = Inst(Op::Mov, SegmentRegisterRm);
+    table[0x8D] = Inst(Op::Lea, LoadAddress);
+    table[0x8E] = Inst(Op::Mov, SegmentRegisterRm);
+    table[0x8F] = Group(&GROUP_8F);
+
+    table[0x90] = Inst(Op::Nop, Implied);
+    fill(&mut table, 0x91, 0x97, Inst(Op::Xchg, AccumulatorRegister));
+    table[0x98] = Inst(Op::Cbw, Implied);
+    table[0x99] = Inst(Op::Cwd, Implied);
+    table[0x9A] = Inst(Op::Call, FarPointer);
+    table[0x9B] = Inst(Op::Wait, Implied);
+    table[0x9C] = Inst(Op::Pushf, Implied);
+    table[0x9D] = Inst(Op::Popf, Implied);
+    table[0x9E] = Inst(Op::Sahf, Implied);
+    table[0x9F] = Inst(Op::Lahf, Implied);
+
+    fill(&mut table, 0xA0, 0xA3, Inst(Op::Mov, AccumulatorMemory));
+    fill(&mut table, 0xA4, 0xA5, Inst(Op::Movs, String));
+    fill(&mut table, 0xA6, 0xA7, Inst(Op::Cmps, String));
+    fill(&mut table, 0xA8, 0xA9, Inst(Op::Test, AccumulatorImmediate));
+    fill(&mut table, 0xAA, 0xAB, Inst(Op::Stos, String));
+    fill(&mut table, 0xAC, 0xAD, Inst(Op::Lods, String));
+    fill(&mut table, 0xAE, 0xAF, Inst(Op::Scas, String));
+
+    fill(&mut table, 0xB0, 0xBF, Inst(Op::Mov, RegImmediate));
+
+    table[0xC2] = Inst(Op::Ret, Immediate16);
+    table[0xC3] = Inst(Op::Ret, Implied);
+    table[0xC4] = Inst(Op::Les, LoadAddress);
+    table[0xC5] = Inst(Op::Lds, LoadAddress);
+    table[0xC6] = Group(&GROUP_C6);
+    table[0xC7] = Group(&GROUP_C6);
+    table[0xCA] = Inst(Op::Retf, Immediate16);
+    table[0xCB] = Inst(Op::Retf, Implied);
+    table[0xCC] = Inst(Op::Int3, Implied);
+    table[0xCD] = Inst(Op::Int, Immediate8);
+    table[0xCE] = Inst(Op::Into, Implied);
+    table[0xCF] = Inst(Op::Iret, Implied);
+
+    fill(&mut table, 0xD0, 0xD3, Group(&GROUP_D0));
+    table[0xD4] = Inst(Op::Aam, Immediate8);
+    table[0xD5] = Inst(Op::Aad, Immediate8);
+    table[0xD7] = Inst(Op::Xlat, Implied);
+    fill(&mut table, 0xD8, 0xDF, Inst(Op::Esc, Escape));
+
+    table[0xE0] = Inst(Op::Loopnz, ShortJump);
+    table[0xE1] = Inst(Op::Loopz, ShortJump);
+    table[0xE2] = Inst(Op::Loop, ShortJump);
+    table[0xE3] = Inst(Op::Jcxz, ShortJump);
+    fill(&mut table, 0xE4, 0xE5, Inst(Op::In, PortImmediate));
+    fill(&mut table, 0xE6, 0xE7, Inst(Op::Out, PortImmediate));
+    table[0xE8] = Inst(Op::Call, NearJump);
+    table[0xE9] = Inst(Op::Jmp, NearJump);
+    table[0xEA] = Inst(Op::Jmp, FarPointer);
+    table[0xEB] = Inst(Op::Jmp, ShortJump);
+    fill(&mut table, 0xEC, 0xED, Inst(Op::In, PortDx));
+    fill(&mut table, 0xEE, 0xEF, Inst(Op::Out, PortDx));
+
+    table[0xF0] = Entry::Prefix(Prefix::Lock);
+    table[0xF2] = Entry::Prefix(Prefix::Repeat { while_zero: false });
+    table[0xF3] = Entry::Prefix(Prefix::Repeat { while_zero: true });
+    table[0xF4] = Inst(Op::Hlt, Implied);
+    table[0xF5] = Inst(Op::Cmc, Implied);
+    fill(&mut table, 0xF6, 0xF7, Group(&GROUP_F6));
+    table[0xF8] = Inst(Op::Clc, Implied);
+    table[0xF9] = Inst(Op::Stc, Implied);
+    table[0xFA] = Inst(Op::Cli, Implied);
+    table[0xFB] = Inst(Op::Sti, Implied);
+    table[0xFC] = Inst(Op::Cld, Implied);
+    table[0xFD] = Inst(Op::Std, Implied);
+    table[0xFE] = Group(&GROUP_FE);
+    table[0xFF] = Group(&GROUP_FF);
+
+    table
+}
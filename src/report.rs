@@ -0,0 +1,265 @@
+//! Machine-readable output: one record per disassembled or simulated instruction, printed as JSON
+//! or CSV for analysis scripts.
+
+use crate::cpu_state::{format_flags, segmented_address};
+use crate::disassembler::{collect_labels, jump_operand, Line};
+use crate::instruction::Instruction;
+use crate::simulator::{StepTrace, LOAD_SEGMENT};
+use serde::{Serialize, Serializer};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// How the result is printed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// NASM source, with simulation side effects as comments
+    #[default]
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!(
+                "unknown format {:?}, expected text, json or csv",
+                s
+            )),
+        }
+    }
+}
+
+/// Everything known about a single instruction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Record {
+    pub offset: usize,
+    /// The bytes the instruction was decoded from, printed as hex
+    #[serde(serialize_with = "hex_bytes")]
+    pub bytes: Vec<u8>,
+    /// Mnemonic along with any prefix or jump kind, e.g. `lock xchg`, `rep movsb` or `jmp`
+    pub mnemonic: String,
+    pub operands: Vec<String>,
+    pub length: usize,
+    /// Why the bytes couldn't be decoded, for `db` records
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Side effects of executing the instruction, only filled in when simulating
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<StepRecord>,
+}
+
+/// What executing an instruction changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StepRecord {
+    pub registers: Vec<RegisterChange>,
+    /// FLAGS before and after as letters, only present when they changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<FlagsChange>,
+    pub jump_taken: bool,
+    /// Estimated clocks for the instruction, only present with `--cycles`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clocks: Option<u32>,
+    /// Running clock total including this instruction, only present with `--cycles`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_clocks: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RegisterChange {
+    pub register: String,
+    pub before: u16,
+    pub after: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlagsChange {
+    pub before: String,
+    pub after: String,
+}
+
+fn hex_bytes<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_bytes(bytes))
+}
+
+/// Formats bytes as space separated hex pairs, e.g. `89 D9`
+pub fn format_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Record {
+    /// Builds the record for `instruction` found at `offset`, starting with `bytes`. A relative
+    /// target is printed as `jump_operand` when there is one, as in the listing
+    fn new(
+        bytes: &[u8],
+        offset: usize,
+        instruction: &Instruction,
+        jump_operand: Option<String>,
+    ) -> Self {
+        let length = instruction.size;
+
+        Record {
+            offset,
+            bytes: bytes[..length].to_vec(),
+            mnemonic: instruction.full_mnemonic(),
+            operands: match jump_operand {
+                Some(target) => vec![target],
+                None => instruction.formatted_operands(),
+            },
+            length,
+            error: None,
+            step: None,
+        }
+    }
+}
+
+/// One record per line of a disassembly, with jump targets printed as labels like in the text
+/// output. Undecoded bytes become a `db` record carrying the decode error
+pub fn disassembly_records(bytes: &[u8], lines: &[Line]) -> Vec<Record> {
+    let labels = collect_labels(lines);
+
+    lines
+        .iter()
+        .map(|line| match *line {
+            Line::Instruction {
+                offset,
+                instruction,
+            } => {
+                let target = jump_operand(offset, &instruction, &labels);
+                Record::new(&bytes[offset..], offset, &instruction, target)
+            }
+            Line::Undecoded {
+                offset,
+                length,
+                error,
+            } => {
+                let undecoded = &bytes[offset..offset + length];
+
+                Record {
+                    offset,
+                    bytes: undecoded.to_vec(),
+                    mnemonic: "db".to_string(),
+                    operands: undecoded
+                        .iter()
+                        .map(|byte| format!("0x{:02X}", byte))
+                        .collect(),
+                    length,
+                    error: Some(error.to_string()),
+                    step: None,
+                }
+            }
+        })
+        .collect()
+}
+
+/// The record for an instruction of the [`load`]ed `program` that was just simulated. The offset
+/// is the IP it ran at
+///
+/// [`load`]: crate::simulator::load
+pub fn step_record(
+    program: &[u8],
+    instruction: &Instruction,
+    trace: &StepTrace,
+    total_clocks: Option<u64>,
+) -> Record {
+    let ip = trace.ip_change.0;
+    let position = segmented_address(trace.code_segment, ip) - segmented_address(LOAD_SEGMENT, 0);
+    let bytes = &program[position as usize..];
+    let mut record = Record::new(bytes, ip as usize, instruction, None);
+
+    let (flags_before, flags_after) = trace.flags_change;
+
+    record.step = Some(StepRecord {
+        registers: trace
+            .register_changes
+            .iter()
+            .map(|(reg, before, after)| RegisterChange {
+                register: reg.to_string(),
+                before: *before,
+                after: *after,
+            })
+            .collect(),
+        flags: (flags_before != flags_after).then(|| FlagsChange {
+            before: format_flags(flags_before),
+            after: format_flags(flags_after),
+        }),
+        jump_taken: trace.jump_taken,
+        clocks: trace.clocks.map(|clocks| clocks.total()),
+        total_clocks: trace.clocks.and(total_clocks),
+    });
+
+    record
+}
+
+/// Prints the records as a JSON array
+pub fn to_json(records: &[Record]) -> String {
+    serde_json::to_string_pretty(records).expect("records always serialize")
+}
+
+/// Prints the records as CSV with a header row. Operands are separated by `; ` and register
+/// changes are written as `cx:0x0000->0x0001`. The simulation columns are only present if the
+/// records came from a simulation
+pub fn to_csv(records: &[Record]) -> String {
+    let simulated = records.iter().any(|record| record.step.is_some());
+    let mut csv = String::from("offset,bytes,mnemonic,operands,length,error");
+
+    if simulated {
+        csv.push_str(",registers,flags,jump_taken,clocks,total_clocks");
+    }
+    csv.push('\n');
+
+    for record in records {
+        let mut fields = vec![
+            record.offset.to_string(),
+            format_bytes(&record.bytes),
+            record.mnemonic.clone(),
+            record.operands.join("; "),
+            record.length.to_string(),
+            record.error.clone().unwrap_or_default(),
+        ];
+
+        if let Some(step) = &record.step {
+            let registers: Vec<String> = step
+                .registers
+                .iter()
+                .map(|change| {
+                    format!(
+                        "{}:0x{:04x}->0x{:04x}",
+                        change.register, change.before, change.after
+                    )
+                })
+                .collect();
+
+            fields.push(registers.join(" "));
+            fields.push(match &step.flags {
+                Some(flags) => format!("{}->{}", flags.before, flags.after),
+                None => String::new(),
+            });
+            fields.push(step.jump_taken.to_string());
+            fields.push(step.clocks.map(|c| c.to_string()).unwrap_or_default());
+            fields.push(step.total_clocks.map(|c| c.to_string()).unwrap_or_default());
+        }
+
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        writeln!(csv, "{}", fields.join(",")).unwrap();
+    }
+
+    csv
+}
+
+/// Quotes a field if it contains anything CSV gives a meaning to
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
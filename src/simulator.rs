@@ -0,0 +1,927 @@
+use crate::alu;
+use crate::cpu_state::*;
+use crate::cycles::{self, Clocks};
+use crate::decoder::{decode_at, DecodeError};
+use crate::instruction::*;
+use crate::memory::MEMORY_SIZE;
+
+/// Default cap on the number of instructions a single [`run`] will execute
+pub const DEFAULT_MAX_INSTRUCTIONS: usize = 1_000_000;
+
+/// Segment [`load`] puts programs in. With DS left at 0, it keeps the code out of the way of the
+/// interrupt vector table and of any data the program addresses
+pub const LOAD_SEGMENT: u16 = 0x1000;
+
+/// Why [`run`] stopped executing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// IP moved past the end of the program
+    EndOfProgram,
+    /// A `hlt` instruction was executed
+    Halted,
+    /// The instruction limit was reached, most likely because the program loops forever
+    InstructionLimit,
+    /// The bytes at IP couldn't be decoded
+    DecodeError(DecodeError),
+    /// The instruction at IP needs hardware that isn't simulated, like I/O ports or a coprocessor
+    Unsupported(Op),
+}
+
+/// Observable side effects of simulating a single instruction
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StepTrace {
+    /// Register name, value before and value after
+    pub register_changes: Vec<(Reg, u16, u16)>,
+    /// FLAGS before and after the instruction
+    pub flags_change: (u16, u16),
+    /// Whether a jump/loop instruction transferred control to its target
+    pub jump_taken: bool,
+    /// IP before and after the instruction
+    pub ip_change: (u16, u16),
+    /// CS the instruction was fetched from
+    pub code_segment: u16,
+    /// Estimated clocks, only filled in when the CPU state is counting them
+    pub clocks: Option<Clocks>,
+}
+
+/// Copies `program` into memory at `LOAD_SEGMENT:0000` and points CS:IP at its first byte. The
+/// other segment registers are left alone
+pub fn load(cpu_state: &mut CpuState, program: &[u8]) {
+    cpu_state
+        .memory
+        .load(segmented_address(LOAD_SEGMENT, 0), program);
+    cpu_state.set_new_register_value(Reg::Cs, LOAD_SEGMENT);
+    cpu_state.ip = 0;
+}
+
+/// Fetches, decodes and executes instructions from CS:IP until execution leaves `program`, halts,
+/// or `max_instructions` have been executed. `program` has to be [`load`]ed already, it only tells
+/// where the code ends. `on_step` is called after every executed instruction.
+pub fn run<F>(
+    cpu_state: &mut CpuState,
+    program: &[u8],
+    max_instructions: usize,
+    mut on_step: F,
+) -> StopReason
+where
+    F: FnMut(&CpuState, &Instruction, &StepTrace),
+{
+    let mut executed = 0;
+
+    loop {
+        // Running out of program takes precedence over running out of instructions
+        if executed == max_instructions {
+            return match fetch(cpu_state, program) {
+                Ok(_) => StopReason::InstructionLimit,
+                Err(reason) => reason,
+            };
+        }
+
+        let (instruction, trace) = match step(cpu_state, program) {
+            Ok(step) => step,
+            Err(reason) => return reason,
+        };
+        executed += 1;
+        on_step(cpu_state, &instruction, &trace);
+
+        if instruction.op == Op::Hlt {
+            return StopReason::Halted;
+        }
+    }
+}
+
+/// Decodes the instruction at CS:IP, or tells why there is nothing left to execute
+pub fn fetch(cpu_state: &CpuState, program: &[u8]) -> Result<Instruction, StopReason> {
+    fetch_at(cpu_state, program, cpu_state.get_ip())
+}
+
+/// Decodes the instruction at CS:`offset` from memory. Anything outside of the loaded `program`
+/// counts as its end, and decode errors are reported at offsets within CS
+pub fn fetch_at(
+    cpu_state: &CpuState,
+    program: &[u8],
+    offset: u16,
+) -> Result<Instruction, StopReason> {
+    let start = segmented_address(LOAD_SEGMENT, 0) as usize;
+    let end = (start + program.len()).min(MEMORY_SIZE);
+    let segment_start = segmented_address(cpu_state.get_register_value(Reg::Cs), 0) as usize;
+    let address = segment_start + offset as usize;
+
+    if !(start..end).contains(&address) {
+        return Err(StopReason::EndOfProgram);
+    }
+
+    let code = &cpu_state.memory.as_bytes()[segment_start..end];
+    decode_at(code, offset as usize).map_err(StopReason::DecodeError)
+}
+
+/// Fetches and executes a single instruction. Unlike [`run`], executing `hlt` isn't an error, so
+/// callers have to check for it themselves
+pub fn step(
+    cpu_state: &mut CpuState,
+    program: &[u8],
+) -> Result<(Instruction, StepTrace), StopReason> {
+    let instruction = fetch(cpu_state, program)?;
+    if !is_supported(instruction.op) {
+        return Err(StopReason::Unsupported(instruction.op));
+    }
+
+    log::debug!(
+        "{:04X}:{:04X}: executing {}",
+        cpu_state.get_register_value(Reg::Cs),
+        cpu_state.get_ip(),
+        instruction
+    );
+
+    let trace = execute(cpu_state, &instruction);
+    Ok((instruction, trace))
+}
+
+/// in and out have no devices to talk to and esc has no coprocessor, so stepping stops there
+/// rather than carry on with a made up result
+fn is_supported(op: Op) -> bool {
+    !matches!(op, Op::In | Op::Out | Op::Esc)
+}
+
+/// Appends the side effects of a simulated instruction as comments
+pub fn push_trace(assembled_file_str: &mut String, cpu_state: &CpuState, trace: &StepTrace) {
+    if let (Some(clocks), Some(total_clocks)) = (trace.clocks, cpu_state.total_clocks) {
+        assembled_file_str.push_str(&format!("; Clocks: +{} = {}", clocks.total(), total_clocks));
+
+        if clocks.ea != 0 || clocks.penalty != 0 {
+            assembled_file_str.push_str(&format!(" ({})", clocks));
+        }
+
+        assembled_file_str.push('\n');
+    }
+
+    for (reg, current_value, new_value) in &trace.register_changes {
+        assembled_file_str.push_str(&format!(
+            "; {}: 0x{:02x} -> 0x{:02x}\n",
+            reg, current_value, new_value
+        ));
+    }
+
+    let (flags_before, flags_after) = trace.flags_change;
+    if flags_before != flags_after {
+        assembled_file_str.push_str(&format!(
+            "; flags:{}->{}\n",
+            format_flags(flags_before),
+            format_flags(flags_after)
+        ));
+    }
+
+    if trace.jump_taken {
+        assembled_file_str.push_str(&format!(
+            "; Jump taken, ip: 0x{:02x} -> 0x{:02x}\n",
+            trace.ip_change.0, trace.ip_change.1
+        ));
+    }
+}
+
+/// Executes `instruction` against `cpu_state`, advancing IP past it or to the jump target.
+/// Instructions [`step`] refuses as unsupported only advance IP.
+pub fn execute(cpu_state: &mut CpuState, instruction: &Instruction) -> StepTrace {
+    let mut trace = StepTrace::default();
+    let ip_before = cpu_state.get_ip();
+    let code_segment = cpu_state.get_register_value(Reg::Cs);
+    let flags_before = cpu_state.flags;
+    // Shifts by CL take longer the larger the count, and where the memory operand lands decides
+    // whether words have to be split. Both have to be read before the instruction changes them
+    let shift_count = cpu_state.get_register_value(Reg::Cl) as u8;
+    let (si, di) = (
+        cpu_state.get_register_value(Reg::Si),
+        cpu_state.get_register_value(Reg::Di),
+    );
+    let effective_address =
+        instruction
+            .operands
+            .iter()
+            .flatten()
+            .find_map(|operand| match operand {
+                Operand::Memory(address) => Some(cpu_state.effective_address(address)),
+                _ => None,
+            });
+
+    // IP always points at the next instruction while the current one executes, which is also
+    // what jump displacements are relative to
+    cpu_state.modify_ip(instruction.size as i16);
+
+    let mut iterated = true;
+
+    match instruction.operands {
+        _ if instruction.op.is_string() => {
+            iterated = execute_string_op(cpu_state, instruction, ip_before, &mut trace)
+        }
+        _ if is_software_interrupt(instruction.op) => {
+            execute_software_interrupt(cpu_state, instruction, &mut trace)
+        }
+        _ if is_stack_op(instruction.op) => execute_stack_op(cpu_state, instruction, &mut trace),
+        [Some(operand), None] if is_multiply_or_divide(instruction.op) => {
+            execute_multiply_or_divide(cpu_state, instruction, operand, &mut trace)
+        }
+        _ if is_accumulator_op(instruction.op) => {
+            execute_accumulator_op(cpu_state, instruction, &mut trace)
+        }
+        [Some(Operand::Relative(displacement)), None] => {
+            execute_jump(cpu_state, instruction.op, displacement, &mut trace)
+        }
+        [Some(destination @ (Operand::Register(_) | Operand::Memory(_))), source] => {
+            execute_data_op(cpu_state, instruction, destination, source, &mut trace)
+        }
+        [None, None] => execute_flag_op(cpu_state, instruction.op, &mut trace),
+        _ => {}
+    }
+
+    trace.ip_change = (ip_before, cpu_state.get_ip());
+    trace.code_segment = code_segment;
+    trace.flags_change = (flags_before, cpu_state.flags);
+
+    if let Some(total_clocks) = cpu_state.total_clocks.as_mut() {
+        let model = cpu_state.cpu_model;
+        let clocks = match instruction.op.is_string() {
+            true => {
+                let finished = trace.ip_change.1 != ip_before;
+                cycles::string_clocks(instruction, model, si, di, iterated, finished)
+            }
+            false => cycles::estimate(
+                instruction,
+                model,
+                trace.jump_taken,
+                shift_count,
+                effective_address,
+            ),
+        };
+        *total_clocks += clocks.total() as u64;
+        trace.clocks = Some(clocks);
+    }
+
+    trace
+}
+
+fn execute_data_op(
+    cpu_state: &mut CpuState,
+    instruction: &Instruction,
+    destination: Operand,
+    source: Option<Operand>,
+    trace: &mut StepTrace,
+) {
+    let op = instruction.op;
+    let is_wide = instruction.is_wide();
+
+    let new_value = match (op, source) {
+        // lea wants the address itself rather than what's stored there
+        (Op::Lea, Some(Operand::Memory(address))) => cpu_state.effective_address(&address),
+        (Op::Mov, Some(source)) => read_operand(cpu_state, instruction, source),
+        (Op::Xchg, Some(source)) => {
+            let source_value = read_operand(cpu_state, instruction, source);
+            let current_value = read_operand(cpu_state, instruction, destination);
+            write_operand(cpu_state, instruction, source, current_value, trace);
+            source_value
+        }
+        // The pointer's segment goes into DS or ES, its offset into the register
+        (Op::Lds | Op::Les, Some(Operand::Memory(address))) => {
+            let (segment, offset) = read_far_pointer(cpu_state, instruction, &address);
+            let segment_register = if op == Op::Lds { Reg::Ds } else { Reg::Es };
+            set_register(cpu_state, segment_register, segment, trace);
+            offset
+        }
+        _ if alu::is_alu_op(op) => {
+            let source_value =
+                source.map_or(0, |source| read_operand(cpu_state, instruction, source));
+            let current_value = read_operand(cpu_state, instruction, destination);
+
+            alu::compute(
+                op,
+                current_value,
+                source_value,
+                is_wide,
+                &mut cpu_state.flags,
+            )
+        }
+        _ => return,
+    };
+
+    if alu::writes_result(op) {
+        write_operand(cpu_state, instruction, destination, new_value, trace);
+    }
+}
+
+/// Reads the value of a register, memory or immediate operand of `instruction`. Memory is read as
+/// a byte or a word depending on the operation size
+fn read_operand(cpu_state: &CpuState, instruction: &Instruction, operand: Operand) -> u16 {
+    match operand {
+        Operand::Register(reg) => cpu_state.get_register_value(reg),
+        Operand::Memory(address) => {
+            let address = cpu_state.physical_address(&address, instruction.segment_override);
+            cpu_state.memory.read(address, instruction.is_wide())
+        }
+        Operand::Immediate(value) => value,
+        Operand::Relative(_) | Operand::FarPointer { .. } => {
+            unreachable!("{:?} is not a data operand", operand)
+        }
+    }
+}
+
+fn write_operand(
+    cpu_state: &mut CpuState,
+    instruction: &Instruction,
+    operand: Operand,
+    value: u16,
+    trace: &mut StepTrace,
+) {
+    match operand {
+        Operand::Register(reg) => set_register(cpu_state, reg, value, trace),
+        Operand::Memory(address) => {
+            let address = cpu_state.physical_address(&address, instruction.segment_override);
+            cpu_state
+                .memory
+                .write(address, value, instruction.is_wide());
+        }
+        _ => unreachable!("{:?} can't be written to", operand),
+    }
+}
+
+/// Instructions without operands that operate on the flags
+fn execute_flag_op(cpu_state: &mut CpuState, op: Op, trace: &mut StepTrace) {
+    match op {
+        Op::Clc => cpu_state.set_flag(Flag::Carry, false),
+        Op::Stc => cpu_state.set_flag(Flag::Carry, true),
+        Op::Cmc => cpu_state.set_flag(Flag::Carry, !cpu_state.get_flag(Flag::Carry)),
+        Op::Cld => cpu_state.set_flag(Flag::Direction, false),
+        Op::Std => cpu_state.set_flag(Flag::Direction, true),
+        Op::Cli => cpu_state.set_flag(Flag::Interrupt, false),
+        Op::Sti => cpu_state.set_flag(Flag::Interrupt, true),
+
+        // lahf/sahf move SF, ZF, AF, PF and CF, which all live in the low byte of FLAGS
+        Op::Lahf => {
+            // Bit 1 of FLAGS is reserved and always reads as 1
+            let ah = (cpu_state.flags & SAHF_MASK) | 0b10;
+            set_register(cpu_state, Reg::Ah, ah, trace);
+        }
+        Op::Sahf => {
+            let ah = cpu_state.get_register_value(Reg::Ah);
+            cpu_state.flags = (cpu_state.flags & !SAHF_MASK) | (ah & SAHF_MASK);
+        }
+
+        _ => {}
+    }
+}
+
+const SAHF_MASK: u16 = SIGN_FLAG | ZERO_FLAG | AUXILIARY_CARRY_FLAG | PARITY_FLAG | CARRY_FLAG;
+
+/// Bits of FLAGS that mean something. The rest are reserved, and bits 1 and 12-15 always read as
+/// 1 on the 8086
+const DEFINED_FLAGS: u16 = OVERFLOW_FLAG | DIRECTION_FLAG | INTERRUPT_FLAG | TRAP_FLAG | SAHF_MASK;
+const RESERVED_FLAGS_SET: u16 = 0xF002;
+
+fn is_stack_op(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Push
+            | Op::Pop
+            | Op::Pushf
+            | Op::Popf
+            | Op::Call
+            | Op::Jmp
+            | Op::Ret
+            | Op::Retf
+            | Op::Iret
+    )
+}
+
+/// Instructions that go through the stack at SS:SP, along with jmp, which takes the same kinds
+/// of targets as call. Only SP is recorded in the trace while the stack is manipulated, once,
+/// before whatever the instruction then writes
+fn execute_stack_op(cpu_state: &mut CpuState, instruction: &Instruction, trace: &mut StepTrace) {
+    let sp_before = cpu_state.get_register_value(Reg::Sp);
+    let record_sp = |cpu_state: &CpuState, trace: &mut StepTrace| {
+        let sp = cpu_state.get_register_value(Reg::Sp);
+        trace.register_changes.push((Reg::Sp, sp_before, sp));
+    };
+
+    match (instruction.op, instruction.operands[0]) {
+        (Op::Push, Some(source)) => {
+            // The 8086 decrements SP before reading the operand, so `push sp` pushes the new value
+            move_stack_pointer(cpu_state, -2);
+            let value = read_operand(cpu_state, instruction, source);
+            write_stack_top(cpu_state, value);
+            record_sp(cpu_state, trace);
+        }
+        (Op::Pop, Some(destination)) => {
+            let value = pop(cpu_state);
+            record_sp(cpu_state, trace);
+            write_operand(cpu_state, instruction, destination, value, trace);
+        }
+        (Op::Pushf, None) => {
+            push(cpu_state, cpu_state.flags | RESERVED_FLAGS_SET);
+            record_sp(cpu_state, trace);
+        }
+        (Op::Popf, None) => {
+            cpu_state.flags = pop(cpu_state) & DEFINED_FLAGS;
+            record_sp(cpu_state, trace);
+        }
+
+        (op @ (Op::Call | Op::Jmp), Some(target)) => {
+            // The target has to be read before the return address goes onto the stack
+            let (segment, offset) = transfer_target(cpu_state, instruction, target);
+
+            if op == Op::Call {
+                if segment.is_some() {
+                    push(cpu_state, cpu_state.get_register_value(Reg::Cs));
+                }
+                push(cpu_state, cpu_state.get_ip());
+                record_sp(cpu_state, trace);
+            }
+
+            if let Some(segment) = segment {
+                set_register(cpu_state, Reg::Cs, segment, trace);
+            }
+            cpu_state.ip = offset;
+            trace.jump_taken = true;
+        }
+
+        (Op::Ret | Op::Retf, release) => {
+            cpu_state.ip = pop(cpu_state);
+            let cs = (instruction.op == Op::Retf).then(|| pop(cpu_state));
+
+            // `ret n` also drops the n bytes of arguments the caller pushed
+            if let Some(Operand::Immediate(bytes)) = release {
+                move_stack_pointer(cpu_state, bytes as i16);
+            }
+
+            record_sp(cpu_state, trace);
+            if let Some(cs) = cs {
+                set_register(cpu_state, Reg::Cs, cs, trace);
+            }
+
+            trace.jump_taken = true;
+        }
+
+        // The reverse of what `interrupt` pushes
+        (Op::Iret, None) => {
+            cpu_state.ip = pop(cpu_state);
+            let cs = pop(cpu_state);
+            cpu_state.flags = pop(cpu_state) & DEFINED_FLAGS;
+
+            record_sp(cpu_state, trace);
+            set_register(cpu_state, Reg::Cs, cs, trace);
+            trace.jump_taken = true;
+        }
+
+        _ => {}
+    }
+}
+
+/// Where a call or jmp goes, as the new CS if it is a far transfer and the new IP. Near transfers
+/// leave CS alone
+fn transfer_target(
+    cpu_state: &CpuState,
+    instruction: &Instruction,
+    target: Operand,
+) -> (Option<u16>, u16) {
+    match target {
+        Operand::FarPointer { segment, offset } => (Some(segment), offset),
+        Operand::Memory(address) if instruction.is_far() => {
+            let (segment, offset) = read_far_pointer(cpu_state, instruction, &address);
+            (Some(segment), offset)
+        }
+        Operand::Relative(displacement) => {
+            (None, cpu_state.get_ip().wrapping_add_signed(displacement))
+        }
+        _ => (None, read_operand(cpu_state, instruction, target)),
+    }
+}
+
+/// Vectors of the interrupts raised by `int3` and by `into` on overflow
+const BREAKPOINT_VECTOR: u8 = 3;
+const OVERFLOW_VECTOR: u8 = 4;
+
+fn is_software_interrupt(op: Op) -> bool {
+    matches!(op, Op::Int | Op::Int3 | Op::Into)
+}
+
+/// int n, int3 and into, which only interrupts if OF is set
+fn execute_software_interrupt(
+    cpu_state: &mut CpuState,
+    instruction: &Instruction,
+    trace: &mut StepTrace,
+) {
+    let vector = match (instruction.op, instruction.operands[0]) {
+        (Op::Int, Some(Operand::Immediate(vector))) => vector as u8,
+        (Op::Int3, None) => BREAKPOINT_VECTOR,
+        (Op::Into, None) if cpu_state.get_flag(Flag::Overflow) => OVERFLOW_VECTOR,
+        _ => return,
+    };
+
+    interrupt(cpu_state, vector, trace);
+}
+
+/// Vector of the interrupt raised when a division fails
+const DIVIDE_ERROR_VECTOR: u8 = 0;
+
+fn is_multiply_or_divide(op: Op) -> bool {
+    matches!(op, Op::Mul | Op::Imul | Op::Div | Op::Idiv)
+}
+
+/// mul/imul/div/idiv, which work on AX, or DX:AX for words, with `operand` as the other side.
+/// Only CF and OF are defined after a multiplication and no flags after a division, so the rest
+/// are left alone
+fn execute_multiply_or_divide(
+    cpu_state: &mut CpuState,
+    instruction: &Instruction,
+    operand: Operand,
+    trace: &mut StepTrace,
+) {
+    let is_wide = instruction.is_wide();
+    let source = read_operand(cpu_state, instruction, operand);
+
+    // The low and high halves of the double-size accumulator
+    let (low, high) = match is_wide {
+        true => (Reg::Ax, Reg::Dx),
+        false => (Reg::Al, Reg::Ah),
+    };
+    let bits = if is_wide { 16 } else { 8 };
+    let mask = if is_wide { 0xFFFF } else { 0xFF };
+    let accumulator = cpu_state.get_register_value(low) as u32;
+
+    match instruction.op {
+        Op::Mul | Op::Imul => {
+            let (product, overflow) = match instruction.op {
+                Op::Mul => {
+                    let product = accumulator * source as u32;
+                    (product, product >> bits != 0)
+                }
+                _ => {
+                    let product = sign_extend(accumulator, bits) * sign_extend(source as u32, bits);
+                    // The product overflows the low half if the high half is more than just its
+                    // sign extended
+                    (
+                        product as u32,
+                        sign_extend(product as u32 & mask, bits) != product,
+                    )
+                }
+            };
+
+            // The byte forms put the whole product in AX
+            set_register(cpu_state, Reg::Ax, product as u16, trace);
+            if is_wide {
+                set_register(cpu_state, Reg::Dx, (product >> 16) as u16, trace);
+            }
+
+            cpu_state.set_flag(Flag::Carry, overflow);
+            cpu_state.set_flag(Flag::Overflow, overflow);
+        }
+
+        Op::Div | Op::Idiv => {
+            let dividend = match is_wide {
+                true => (cpu_state.get_register_value(Reg::Dx) as u32) << 16 | accumulator,
+                false => cpu_state.get_register_value(Reg::Ax) as u32,
+            };
+
+            let result = match instruction.op {
+                Op::Div => divide(dividend, source as u32, mask),
+                _ => divide_signed(dividend, source as u32, bits),
+            };
+
+            match result {
+                Some((quotient, remainder)) => {
+                    set_register(cpu_state, low, quotient, trace);
+                    set_register(cpu_state, high, remainder, trace);
+                }
+                None => interrupt(cpu_state, DIVIDE_ERROR_VECTOR, trace),
+            }
+        }
+
+        _ => {}
+    }
+}
+
+/// Sign extends the low `bits` bits of `value`
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+/// Unsigned division, or `None` if the divisor is zero or the quotient doesn't fit in `mask`
+fn divide(dividend: u32, divisor: u32, mask: u32) -> Option<(u16, u16)> {
+    let quotient = dividend.checked_div(divisor)?;
+
+    match quotient <= mask {
+        true => Some((quotient as u16, (dividend % divisor) as u16)),
+        false => None,
+    }
+}
+
+/// Signed division rounding toward zero, so the remainder takes the sign of the dividend. The
+/// 8086 refuses to produce the most negative quotient (-128 or -32768)
+fn divide_signed(dividend: u32, divisor: u32, bits: u32) -> Option<(u16, u16)> {
+    let dividend = sign_extend(dividend, bits * 2) as i64;
+    let divisor = sign_extend(divisor, bits) as i64;
+    let quotient = dividend.checked_div(divisor)?;
+    let limit = (1 << (bits - 1)) - 1;
+
+    match (-limit..=limit).contains(&quotient) {
+        true => Some((quotient as u16, (dividend % divisor) as u16)),
+        false => None,
+    }
+}
+
+/// String instructions read from DS:SI, unless overridden, and write to ES:DI, stepping both by the
+/// operation size, backwards if DF is set. A repeated instruction runs a single iteration per
+/// step and then leaves IP on itself until the repetition ends, so it can be single-stepped and a
+/// run is counted per iteration. REP stops when CX runs out, and cmps/scas also stop when ZF
+/// doesn't match the prefix. Returns whether an iteration ran, which it doesn't if CX started at 0
+fn execute_string_op(
+    cpu_state: &mut CpuState,
+    instruction: &Instruction,
+    ip_before: u16,
+    trace: &mut StepTrace,
+) -> bool {
+    let repeated = instruction.is_repeated();
+
+    if repeated && cpu_state.get_register_value(Reg::Cx) == 0 {
+        return false;
+    }
+
+    let is_wide = instruction.is_wide();
+    let accumulator = if is_wide { Reg::Ax } else { Reg::Al };
+    let si = cpu_state.get_register_value(Reg::Si);
+    let di = cpu_state.get_register_value(Reg::Di);
+
+    let source_segment = instruction.segment_override.unwrap_or(Reg::Ds);
+    let source = segmented_address(cpu_state.get_register_value(source_segment), si);
+    let destination = segmented_address(cpu_state.get_register_value(Reg::Es), di);
+
+    let op = instruction.op;
+    match op {
+        Op::Movs => {
+            let value = cpu_state.memory.read(source, is_wide);
+            cpu_state.memory.write(destination, value, is_wide);
+        }
+        Op::Cmps | Op::Scas => {
+            let left = match op {
+                Op::Cmps => cpu_state.memory.read(source, is_wide),
+                _ => cpu_state.get_register_value(accumulator),
+            };
+            let right = cpu_state.memory.read(destination, is_wide);
+            alu::compute(Op::Cmp, left, right, is_wide, &mut cpu_state.flags);
+        }
+        Op::Lods => {
+            let value = cpu_state.memory.read(source, is_wide);
+            set_register(cpu_state, accumulator, value, trace);
+        }
+        Op::Stos => {
+            let value = cpu_state.get_register_value(accumulator);
+            cpu_state.memory.write(destination, value, is_wide);
+        }
+        _ => {}
+    }
+
+    let size: i16 = if is_wide { 2 } else { 1 };
+    let delta = match cpu_state.get_flag(Flag::Direction) {
+        true => -size,
+        false => size,
+    };
+
+    if matches!(op, Op::Movs | Op::Cmps | Op::Lods) {
+        set_register(cpu_state, Reg::Si, si.wrapping_add_signed(delta), trace);
+    }
+    if op != Op::Lods {
+        set_register(cpu_state, Reg::Di, di.wrapping_add_signed(delta), trace);
+    }
+
+    if repeated {
+        let cx = cpu_state.get_register_value(Reg::Cx).wrapping_sub(1);
+        set_register(cpu_state, Reg::Cx, cx, trace);
+
+        // REPE and REPNE only differ for the instructions that compare
+        let zf_matches = match op {
+            Op::Cmps | Op::Scas => {
+                cpu_state.get_flag(Flag::Zero) == (instruction.flags & FLAG_REP != 0)
+            }
+            _ => true,
+        };
+
+        if cx != 0 && zf_matches {
+            cpu_state.ip = ip_before;
+        }
+    }
+
+    true
+}
+
+fn is_accumulator_op(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Cbw | Op::Cwd | Op::Xlat | Op::Aaa | Op::Aas | Op::Daa | Op::Das | Op::Aam | Op::Aad
+    )
+}
+
+/// Sign extensions, table lookups and decimal adjustments, which all work on AL or AX
+fn execute_accumulator_op(
+    cpu_state: &mut CpuState,
+    instruction: &Instruction,
+    trace: &mut StepTrace,
+) {
+    let al = cpu_state.get_register_value(Reg::Al);
+    let ax = cpu_state.get_register_value(Reg::Ax);
+
+    match (instruction.op, instruction.operands[0]) {
+        (Op::Cbw, _) => set_register(cpu_state, Reg::Ax, sign_extend(al as u32, 8) as u16, trace),
+        (Op::Cwd, _) => {
+            let dx = if ax & 0x8000 != 0 { 0xFFFF } else { 0 };
+            set_register(cpu_state, Reg::Dx, dx, trace);
+        }
+        // AL indexes a table of bytes at BX
+        (Op::Xlat, _) => {
+            let segment = instruction.segment_override.unwrap_or(Reg::Ds);
+            let offset = cpu_state.get_register_value(Reg::Bx).wrapping_add(al);
+            let address = segmented_address(cpu_state.get_register_value(segment), offset);
+            let value = cpu_state.memory.read_u8(address) as u16;
+            set_register(cpu_state, Reg::Al, value, trace);
+        }
+        // aam and aad take the number base as an immediate, which assemblers leave out for 10
+        (op, base) => {
+            let base = match base {
+                Some(Operand::Immediate(base)) => base as u8,
+                _ => 10,
+            };
+
+            match alu::adjust(op, ax, base, &mut cpu_state.flags) {
+                Some(ax) => set_register(cpu_state, Reg::Ax, ax, trace),
+                None => interrupt(cpu_state, DIVIDE_ERROR_VECTOR, trace),
+            }
+        }
+    }
+}
+
+/// Transfers control to the handler of interrupt `vector` through the interrupt vector table at
+/// 0000:0000, where every vector has a 4 byte entry holding the handler's offset and segment.
+/// FLAGS, CS and IP are pushed in that order so `iret` can return, and IF and TF are cleared so
+/// the handler doesn't get interrupted itself. Vectors with a native handler registered skip all
+/// of that and just call it
+fn interrupt(cpu_state: &mut CpuState, vector: u8, trace: &mut StepTrace) {
+    if let Some(handler) = cpu_state.int_handler(vector) {
+        return call_int_handler(cpu_state, &handler, trace);
+    }
+
+    let entry = vector as u32 * 4;
+    let offset = cpu_state.memory.read_u16(entry);
+    let segment = cpu_state.memory.read_u16(entry + 2);
+
+    let sp_before = cpu_state.get_register_value(Reg::Sp);
+    push(cpu_state, cpu_state.flags | RESERVED_FLAGS_SET);
+    push(cpu_state, cpu_state.get_register_value(Reg::Cs));
+    push(cpu_state, cpu_state.get_ip());
+    trace
+        .register_changes
+        .push((Reg::Sp, sp_before, cpu_state.get_register_value(Reg::Sp)));
+
+    cpu_state.set_flag(Flag::Interrupt, false);
+    cpu_state.set_flag(Flag::Trap, false);
+    set_register(cpu_state, Reg::Cs, segment, trace);
+    cpu_state.ip = offset;
+    trace.jump_taken = true;
+}
+
+/// Every register a native interrupt handler could change, in the order changes are recorded
+const WORD_REGISTERS: [Reg; 12] = [
+    Reg::Ax,
+    Reg::Bx,
+    Reg::Cx,
+    Reg::Dx,
+    Reg::Sp,
+    Reg::Bp,
+    Reg::Si,
+    Reg::Di,
+    Reg::Es,
+    Reg::Cs,
+    Reg::Ss,
+    Reg::Ds,
+];
+
+/// Runs a native interrupt handler with the memory lent out to it, and records the registers it
+/// changed in the trace
+fn call_int_handler(cpu_state: &mut CpuState, handler: &IntHandler, trace: &mut StepTrace) {
+    let before = WORD_REGISTERS.map(|reg| cpu_state.get_register_value(reg));
+
+    let mut memory = std::mem::take(&mut cpu_state.memory);
+    handler(cpu_state, &mut memory);
+    cpu_state.memory = memory;
+
+    for (reg, before) in WORD_REGISTERS.into_iter().zip(before) {
+        let after = cpu_state.get_register_value(reg);
+        if after != before {
+            trace.register_changes.push((reg, before, after));
+        }
+    }
+}
+
+/// Physical address of the top of the stack
+fn stack_top(cpu_state: &CpuState) -> u32 {
+    segmented_address(
+        cpu_state.get_register_value(Reg::Ss),
+        cpu_state.get_register_value(Reg::Sp),
+    )
+}
+
+fn move_stack_pointer(cpu_state: &mut CpuState, delta: i16) {
+    let sp = cpu_state.get_register_value(Reg::Sp);
+    cpu_state.set_new_register_value(Reg::Sp, sp.wrapping_add_signed(delta));
+}
+
+/// Decrements SP by 2 and stores `value` at the new SS:SP
+fn push(cpu_state: &mut CpuState, value: u16) {
+    move_stack_pointer(cpu_state, -2);
+    write_stack_top(cpu_state, value);
+}
+
+fn write_stack_top(cpu_state: &mut CpuState, value: u16) {
+    let address = stack_top(cpu_state);
+    cpu_state.memory.write_u16(address, value);
+}
+
+/// Reads the word at SS:SP and increments SP by 2
+fn pop(cpu_state: &mut CpuState) -> u16 {
+    let value = cpu_state.memory.read_u16(stack_top(cpu_state));
+    move_stack_pointer(cpu_state, 2);
+    value
+}
+
+/// Reads the offset and segment of a far pointer stored in memory, offset first
+fn read_far_pointer(
+    cpu_state: &CpuState,
+    instruction: &Instruction,
+    address: &EffectiveAddress,
+) -> (u16, u16) {
+    let segment = instruction
+        .segment_override
+        .unwrap_or(address.base.default_segment());
+    let segment = cpu_state.get_register_value(segment);
+    let offset = cpu_state.effective_address(address);
+
+    let pointer_offset = cpu_state
+        .memory
+        .read_u16(segmented_address(segment, offset));
+    let pointer_segment = cpu_state
+        .memory
+        .read_u16(segmented_address(segment, offset.wrapping_add(2)));
+
+    (pointer_segment, pointer_offset)
+}
+
+/// Writes a register and records the change in the trace
+fn set_register(cpu_state: &mut CpuState, register: Reg, value: u16, trace: &mut StepTrace) {
+    let current_value = cpu_state.get_register_value(register);
+    cpu_state.set_new_register_value(register, value);
+    trace.register_changes.push((
+        register,
+        current_value,
+        cpu_state.get_register_value(register),
+    ));
+}
+
+fn execute_jump(cpu_state: &mut CpuState, op: Op, displacement: i16, trace: &mut StepTrace) {
+    // The LOOP family counts CX down before testing it, without touching any flags
+    if matches!(op, Op::Loop | Op::Loopz | Op::Loopnz) {
+        let cx = cpu_state.get_register_value(Reg::Cx);
+        set_register(cpu_state, Reg::Cx, cx.wrapping_sub(1), trace);
+    }
+
+    let cx = cpu_state.get_register_value(Reg::Cx);
+    let cf = cpu_state.get_flag(Flag::Carry);
+    let pf = cpu_state.get_flag(Flag::Parity);
+    let zf = cpu_state.get_flag(Flag::Zero);
+    let sf = cpu_state.get_flag(Flag::Sign);
+    let of = cpu_state.get_flag(Flag::Overflow);
+
+    let should_jump = match op {
+        Op::Jo => of,
+        Op::Jno => !of,
+        Op::Jb => cf,
+        Op::Jnb => !cf,
+        Op::Je => zf,
+        Op::Jne => !zf,
+        Op::Jbe => cf || zf,
+        Op::Ja => !cf && !zf,
+        Op::Js => sf,
+        Op::Jns => !sf,
+        Op::Jp => pf,
+        Op::Jnp => !pf,
+        Op::Jl => sf != of,
+        Op::Jnl => sf == of,
+        Op::Jle => zf || sf != of,
+        Op::Jg => !zf && sf == of,
+        Op::Loop => cx != 0,
+        Op::Loopz => cx != 0 && zf,
+        Op::Loopnz => cx != 0 && !zf,
+        Op::Jcxz => cx == 0,
+        _ => false,
+    };
+
+    if should_jump {
+        cpu_state.modify_ip(displacement);
+        trace.jump_taken = true;
+    }
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use common::{read_binary, read_source, BINARY_LISTINGS};
+use sim8086::assembler::assemble;
+use sim8086::disassembler::{decode_all, to_assembly};
+
+#[test]
+fn assembles_listing_sources_into_their_binaries() {
+    for listing in BINARY_LISTINGS {
+        let source = read_source(listing);
+        let binary = read_binary(listing);
+
+        assert_eq!(assemble(&source).unwrap(), binary, "{}", listing);
+    }
+}
+
+#[test]
+fn disassembly_reassembles_byte_for_byte() {
+    for listing in BINARY_LISTINGS {
+        let binary = read_binary(listing);
+        let lines = decode_all(&binary, false).unwrap();
+
+        let source = to_assembly(&binary, &lines);
+        assert_eq!(assemble(&source).unwrap(), binary, "{}", listing);
+    }
+}
+
+#[test]
+fn picks_short_or_near_jumps_by_distance() {
+    let far_away = "nop\n".repeat(200);
+    let source = format!("jmp forward\n{}forward:\njmp back\nback:\n", far_away);
+    let bytes = assemble(&source).unwrap();
+
+    assert_eq!(&bytes[..3], &[0xE9, 200, 0]);
+    assert_eq!(&bytes[203..], &[0xEB, 0x00]);
+}
+
+#[test]
+fn reports_the_line_of_an_error() {
+    let error = assemble("mov ax, bx\nfrob ax\n").unwrap_err();
+
+    assert_eq!(error.line, 2);
+    assert!(error.message.contains("frob"));
+}
+
+#[test]
+fn string_instructions_take_prefixes_as_words() {
+    let source = "rep movsb\nrepne scasw\nes lodsb\nrep es movsw\n";
+    let bytes = assemble(source).unwrap();
+
+    assert_eq!(
+        bytes,
+        [0xF3, 0xA4, 0xF2, 0xAF, 0x26, 0xAC, 0xF3, 0x26, 0xA5]
+    );
+
+    let lines = decode_all(&bytes, false).unwrap();
+    assert!(to_assembly(&bytes, &lines).ends_with(source));
+}
+
+#[test]
+fn stray_segment_prefixes_survive_a_round_trip() {
+    // cs in front of an instruction without a memory operand
+    let bytes = [0x2E, 0x8C, 0xC9, 0x26, 0x40];
+    let lines = decode_all(&bytes, false).unwrap();
+    let source = to_assembly(&bytes, &lines);
+
+    assert_eq!(source, "cs mov cx, cs\nes inc ax\n");
+    assert_eq!(assemble(&source).unwrap(), bytes);
+}
+
+#[test]
+fn rejects_malformed_input_without_panicking() {
+    for source in [
+        "mové ax, bx\n",
+        "movsé\n",
+        "mov ax, [bx-0x7fffffffffffffff-0x7fffffffffffffff]\n",
+        "mov ax, [0x7fffffffffffffff+1]\n",
+    ] {
+        let error = assemble(source).unwrap_err();
+        assert_eq!(error.line, 1, "{}", source);
+    }
+}
+
+#[test]
+fn rejects_values_that_dont_fit_their_operand() {
+    for (source, value) in [
+        ("db 1, 300\n", "300"),
+        ("db -129\n", "-129"),
+        ("mov al, 256\n", "256"),
+        ("mov byte [bx], -200\n", "-200"),
+        ("mov ax, 0x10000\n", "0x10000"),
+        ("add word [bx], -32769\n", "-32769"),
+        ("int 256\n", "256"),
+        ("in ax, 0x100\n", "0x100"),
+    ] {
+        let error = assemble(source).unwrap_err();
+        assert!(error.message.contains(value), "{}: {}", source, error);
+    }
+
+    // Either reading of a byte or a word is fine
+    assert_eq!(
+        assemble("db 255, -128\nmov al, -1\nmov ax, 0xFFFF\n").unwrap(),
+        [0xFF, 0x80, 0xB0, 0xFF, 0xB8, 0xFF, 0xFF]
+    );
+}
@@ -0,0 +1,26 @@
+// Each test binary compiles its own copy of this module and only uses part of it
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Listings that ship with both an assembled binary and its source
+pub const BINARY_LISTINGS: [&str; 5] = [
+    "listing_37",
+    "listing_38",
+    "listing_39",
+    "listing_40",
+    "listing_41",
+];
+
+pub fn listing_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(name)
+}
+
+pub fn read_source(listing: &str) -> String {
+    fs::read_to_string(listing_path(&format!("{}.asm", listing))).unwrap()
+}
+
+pub fn read_binary(listing: &str) -> Vec<u8> {
+    fs::read(listing_path(listing)).unwrap()
+}
@@ -0,0 +1,61 @@
+use sim8086::assembler::assemble;
+use sim8086::debugger::{Debugger, Response};
+use sim8086::{CpuState, Reg};
+
+fn output(debugger: &mut Debugger, line: &str) -> String {
+    match debugger.command(line) {
+        Ok(Response::Output(output)) => output,
+        other => panic!("{:?} gave {:?}", line, other),
+    }
+}
+
+#[test]
+fn stops_at_breakpoints_and_steps_from_there() {
+    let program = assemble("mov ax, 1\nmov bx, 2\nadd ax, bx\nmov cx, ax\n").unwrap();
+    let mut debugger = Debugger::new(CpuState::new(), &program);
+
+    assert_eq!(output(&mut debugger, "break 6"), "Breakpoint at 0006\n");
+    assert!(output(&mut debugger, "continue").starts_with("Breakpoint at 0006\n=> 0006:"));
+    assert_eq!(debugger.cpu_state.get_register_value(Reg::Ax), 1);
+
+    let stepped = output(&mut debugger, "step");
+    assert!(stepped.starts_with("0006: add ax, bx\n; ax: 0x01 -> 0x03\n"));
+    assert_eq!(debugger.cpu_state.get_ip(), 8);
+
+    assert_eq!(output(&mut debugger, "continue"), "End of program\n");
+    assert_eq!(debugger.cpu_state.get_register_value(Reg::Cx), 3);
+}
+
+#[test]
+fn sets_registers_and_dumps_memory() {
+    let mut debugger = Debugger::new(CpuState::new(), &[]);
+
+    output(&mut debugger, "set ds 0x100");
+    assert_eq!(output(&mut debugger, "set AX -1"), "ax: 0xFFFF (65535)\n");
+    assert_eq!(output(&mut debugger, "set FLAGS 0x41"), "flags: CZ\n");
+    debugger.cpu_state.memory.write_u16(0x1002, 0xBEEF);
+
+    assert_eq!(debugger.cpu_state.get_register_value(Reg::Ax), 0xFFFF);
+    assert_eq!(output(&mut debugger, "mem 2 2"), "0100:0002  EF BE\n");
+    assert_eq!(
+        output(&mut debugger, "mem 0:0x1000 4"),
+        "0000:1000  00 00 EF BE\n"
+    );
+
+    assert!(debugger.command("set zx 1").is_err());
+    assert_eq!(debugger.command("quit"), Ok(Response::Quit));
+}
+
+#[test]
+fn steps_through_a_rep_one_iteration_at_a_time() {
+    let program = assemble("mov cx, 2\nrep stosb\nnop\n").unwrap();
+    let mut debugger = Debugger::new(CpuState::new(), &program);
+
+    output(&mut debugger, "step 2");
+    assert_eq!(debugger.cpu_state.get_ip(), 3);
+    assert_eq!(debugger.cpu_state.get_register_value(Reg::Cx), 1);
+
+    output(&mut debugger, "step");
+    assert_eq!(debugger.cpu_state.get_ip(), 5);
+    assert_eq!(debugger.cpu_state.get_register_value(Reg::Cx), 0);
+}
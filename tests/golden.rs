@@ -0,0 +1,162 @@
+//! Golden-file checks against the listings that ship with the repository. Decoded binaries are
+//! compared with their reference source, and simulation listings end with a `; Final registers:`
+//! block holding the state the simulator has to arrive at.
+
+mod common;
+
+use common::{read_binary, read_source, BINARY_LISTINGS};
+use sim8086::assembler::assemble;
+use sim8086::cpu_state::format_flags;
+use sim8086::disassembler::{decode_all, to_assembly};
+use sim8086::simulator::{load, run};
+use sim8086::{CpuState, Reg};
+use std::collections::HashMap;
+
+const SIMULATION_LISTINGS: [&str; 3] = ["listing_43", "listing_44", "listing_46"];
+
+/// Spellings NASM accepts for the same instruction, mapped to the ones the disassembler prints
+const ALIASES: [(&str, &str); 8] = [
+    ("jnz", "jne"),
+    ("jz", "je"),
+    ("jnae", "jb"),
+    ("jae", "jnb"),
+    ("jge", "jnl"),
+    ("jnge", "jl"),
+    ("loope", "loopz"),
+    ("loopne", "loopnz"),
+];
+
+/// Reduces assembly to a form where two listings of the same program compare equal: comments,
+/// blank lines and `bits 16` go away, as does all whitespace, and labels are renamed in the order
+/// they first show up.
+fn normalize(source: &str) -> Vec<String> {
+    let mut labels = HashMap::new();
+
+    source
+        .lines()
+        .map(|line| line.split(';').next().unwrap().trim().to_lowercase())
+        .filter(|line| !line.is_empty() && line != "bits 16")
+        .map(|line| normalize_line(&line, &mut labels))
+        .collect()
+}
+
+fn normalize_line(line: &str, labels: &mut HashMap<String, String>) -> String {
+    let mut label_name = |name: &str| {
+        let next = format!("l{}", labels.len());
+        labels.entry(name.to_string()).or_insert(next).clone()
+    };
+
+    if let Some(label) = line.strip_suffix(':') {
+        return format!("{}:", label_name(label));
+    }
+
+    let (mnemonic, operands) = line.split_once(' ').unwrap_or((line, ""));
+    let mnemonic = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == mnemonic)
+        .map_or(mnemonic, |(_, canonical)| *canonical);
+
+    let mut operands: String = operands.chars().filter(|c| !c.is_whitespace()).collect();
+    operands = operands.replace("+0]", "]");
+
+    // Jump operands are labels
+    if mnemonic.starts_with('j') || mnemonic.starts_with("loop") {
+        let (size, target) = match operands.strip_prefix("near") {
+            Some(target) => ("near", target),
+            None => ("", operands.as_str()),
+        };
+        if !target.starts_with('$') {
+            operands = format!("{}{}", size, label_name(target));
+        }
+    }
+
+    // `mov [bx], byte 7` and `mov byte [bx], 7` are the same instruction
+    if let Some((destination, source)) = operands.clone().split_once(',') {
+        for size in ["byte", "word"] {
+            if let Some(immediate) = source.strip_prefix(size) {
+                if destination.starts_with('[') {
+                    operands = format!("{}{},{}", size, destination, immediate);
+                }
+            }
+        }
+    }
+
+    format!("{}{}", mnemonic, operands)
+}
+
+fn register_by_name(name: &str) -> Reg {
+    match name {
+        "ax" => Reg::Ax,
+        "bx" => Reg::Bx,
+        "cx" => Reg::Cx,
+        "dx" => Reg::Dx,
+        "sp" => Reg::Sp,
+        "bp" => Reg::Bp,
+        "si" => Reg::Si,
+        "di" => Reg::Di,
+        "es" => Reg::Es,
+        "cs" => Reg::Cs,
+        "ss" => Reg::Ss,
+        "ds" => Reg::Ds,
+        _ => panic!("unknown register {:?} in expected state", name),
+    }
+}
+
+/// The `name: value` pairs listed after `; Final registers:`, with values kept as written
+fn expected_state(source: &str) -> Vec<(String, String)> {
+    source
+        .lines()
+        .skip_while(|line| !line.starts_with("; Final registers:"))
+        .skip(1)
+        .filter_map(|line| line.strip_prefix(';'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| {
+            let value = value.split_whitespace().next().unwrap_or("");
+            (name.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn decoded_binaries_match_their_sources() {
+    for listing in BINARY_LISTINGS {
+        let binary = read_binary(listing);
+        let lines = decode_all(&binary, false).unwrap();
+
+        let expected = normalize(&read_source(listing));
+        let actual = normalize(&to_assembly(&binary, &lines));
+
+        assert_eq!(actual.len(), expected.len(), "{}", listing);
+        for (number, (actual, expected)) in actual.iter().zip(&expected).enumerate() {
+            assert_eq!(actual, expected, "{} line {}", listing, number + 1);
+        }
+    }
+}
+
+#[test]
+fn simulations_end_in_the_expected_state() {
+    for listing in SIMULATION_LISTINGS {
+        let source = read_source(listing);
+        let program = assemble(&source).unwrap();
+
+        let mut cpu_state = CpuState::new();
+        load(&mut cpu_state, &program);
+        run(&mut cpu_state, &program, usize::MAX, |_, _, _| {});
+
+        let expected = expected_state(&source);
+        assert!(!expected.is_empty(), "{} has no expected state", listing);
+
+        for (name, value) in expected {
+            let actual = match name.as_str() {
+                "flags" => format_flags(cpu_state.flags),
+                "ip" => format!("0x{:04x}", cpu_state.get_ip()),
+                register => format!(
+                    "0x{:04x}",
+                    cpu_state.get_register_value(register_by_name(register))
+                ),
+            };
+
+            assert_eq!(actual, value, "{} {}", listing, name);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use std::process::Command;
+
+use sim8086::disassembler::{decode_all, to_listing};
+
+#[test]
+fn lists_offsets_bytes_and_undecoded_ranges() {
+    // mov cx, bx / two unknown opcodes / mov word [bx + si + 1000], 7 / truncated add
+    let bytes = [
+        0x89, 0xD9, 0xD6, 0xD6, 0xC7, 0x80, 0xE8, 0x03, 0x07, 0x00, 0x81,
+    ];
+    let lines = decode_all(&bytes, true).unwrap();
+
+    assert_eq!(
+        to_listing(&bytes, &lines),
+        "0000: 89 D9             mov cx, bx\n\
+         0002: D6                db 0xD6\n\
+         0003: D6                db 0xD6\n\
+         0004: C7 80 E8 03 07 00 mov word [bx + si + 1000], 7\n\
+         000A: 81                db 0x81\n\
+         \n\
+         ; 3 of 11 bytes undecoded in 2 ranges:\n\
+         ;   0002..0004 (2 bytes): unknown opcode 0xD6 at offset 0x0002\n\
+         ;   000A..000B (1 byte): truncated instruction at offset 0x000A, needs at least 2 bytes\n"
+    );
+}
+
+#[test]
+fn only_merges_undecoded_bytes_that_failed_the_same_way() {
+    // An unknown opcode followed by a mov cut short
+    let bytes = [0x89, 0xD9, 0xD6, 0x89];
+    let lines = decode_all(&bytes, true).unwrap();
+
+    assert!(to_listing(&bytes, &lines).ends_with(
+        "; 2 of 4 bytes undecoded in 2 ranges:\n\
+         ;   0002..0003 (1 byte): unknown opcode 0xD6 at offset 0x0002\n\
+         ;   0003..0004 (1 byte): truncated instruction at offset 0x0003, needs at least 2 bytes\n"
+    ));
+
+    let lines = decode_all(&bytes[..3], true).unwrap();
+    assert!(to_listing(&bytes[..3], &lines).ends_with(
+        "; 1 of 3 bytes undecoded in 1 range:\n\
+         ;   0002..0003 (1 byte): unknown opcode 0xD6 at offset 0x0002\n"
+    ));
+}
+
+#[test]
+fn listing_mode_keeps_going_past_undecodable_bytes() {
+    let path = std::env::temp_dir().join(format!("sim8086-listing-{}", std::process::id()));
+    fs::write(&path, [0xD6, 0x89, 0xD9]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sim8086"))
+        .arg("--listing")
+        .arg("-a")
+        .arg(&path)
+        .arg("-o")
+        .arg(path.with_extension("asm"))
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(path.with_extension("asm")).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("0001: 89 D9             mov cx, bx\n"));
+    assert!(stdout.contains("; 1 of 3 bytes undecoded in 1 range:\n"));
+}
+
+#[test]
+fn long_prefix_runs_keep_their_full_length() {
+    // 300 cs prefixes on an inc ax, then an inc cx
+    let mut bytes = vec![0x2E; 300];
+    bytes.extend_from_slice(&[0x40, 0x41]);
+    let lines = decode_all(&bytes, false).unwrap();
+
+    let offsets: Vec<usize> = lines.iter().map(|line| line.offset()).collect();
+    assert_eq!(offsets, [0, 301]);
+    assert_eq!(lines[0].size(), 301);
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::read_binary;
+use sim8086::assembler::assemble;
+use sim8086::disassembler::decode_all;
+use sim8086::report::{disassembly_records, step_record, to_csv, to_json};
+use sim8086::simulator::{load, run};
+use sim8086::CpuState;
+
+#[test]
+fn disassembly_records_split_instructions_into_fields() {
+    let binary = read_binary("listing_37");
+    let records = disassembly_records(&binary, &decode_all(&binary, false).unwrap());
+
+    let json: serde_json::Value = serde_json::from_str(&to_json(&records)).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([{
+            "offset": 0,
+            "bytes": "89 D9",
+            "mnemonic": "mov",
+            "operands": ["cx", "bx"],
+            "length": 2
+        }])
+    );
+}
+
+#[test]
+fn prefixes_and_keywords_stay_with_the_mnemonic() {
+    let source = "top:\nlock inc word [bx]\nrep movsb\ncs mov cx, cs\nes in al, dx\n\
+                  call far [bx]\njmp near top\n";
+    let binary = assemble(source).unwrap();
+    let records = disassembly_records(&binary, &decode_all(&binary, false).unwrap());
+
+    let fields: Vec<(&str, Vec<&str>)> = records
+        .iter()
+        .map(|record| {
+            let operands = record.operands.iter().map(String::as_str).collect();
+            (record.mnemonic.as_str(), operands)
+        })
+        .collect();
+
+    assert_eq!(
+        fields,
+        [
+            ("lock inc", vec!["word [bx]"]),
+            ("rep movsb", vec![]),
+            ("cs mov", vec!["cx", "cs"]),
+            ("es in", vec!["al", "dx"]),
+            ("call far", vec!["[bx]"]),
+            ("jmp", vec!["near label_0"]),
+        ]
+    );
+}
+
+#[test]
+fn simulation_records_carry_register_and_flag_changes() {
+    let program = assemble("mov cx, 5\nsub cx, 5\n").unwrap();
+    let mut cpu_state = CpuState::new();
+    cpu_state.total_clocks = Some(0);
+    load(&mut cpu_state, &program);
+
+    let mut records = Vec::new();
+    run(
+        &mut cpu_state,
+        &program,
+        10,
+        |cpu_state, instruction, trace| {
+            records.push(step_record(
+                &program,
+                instruction,
+                trace,
+                cpu_state.total_clocks,
+            ))
+        },
+    );
+
+    let csv = to_csv(&records);
+    let lines: Vec<&str> = csv.lines().collect();
+
+    assert_eq!(
+        lines,
+        [
+            "offset,bytes,mnemonic,operands,length,error,registers,flags,jump_taken,clocks,total_clocks",
+            "0,B9 05 00,mov,cx; 5,3,,cx:0x0000->0x0005,,false,4,4",
+            "3,83 E9 05,sub,cx; 5,3,,cx:0x0005->0x0000,->PZ,false,4,8",
+        ]
+    );
+}
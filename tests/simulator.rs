@@ -0,0 +1,484 @@
+use sim8086::assembler::assemble;
+use sim8086::cpu_state::{format_flags, segmented_address};
+use sim8086::cycles::CpuModel;
+use sim8086::simulator::{load, push_trace, run, StopReason, LOAD_SEGMENT};
+use sim8086::{CpuState, Flag, Op, Reg};
+
+/// Assembles, loads and runs `source` from a zeroed CPU
+fn run_source(source: &str) -> CpuState {
+    run_source_with(CpuState::new(), source)
+}
+
+fn run_source_with(mut cpu_state: CpuState, source: &str) -> CpuState {
+    let program = assemble(source).unwrap();
+    load(&mut cpu_state, &program);
+    run(&mut cpu_state, &program, 1000, |_, _, _| {});
+    cpu_state
+}
+
+/// Runs `source` counting clocks, and returns the `; Clocks:` line of every instruction
+fn clock_lines(mut cpu_state: CpuState, source: &str) -> Vec<String> {
+    let program = assemble(source).unwrap();
+    cpu_state.total_clocks = Some(0);
+    load(&mut cpu_state, &program);
+
+    let mut trace_text = String::new();
+    run(&mut cpu_state, &program, 1000, |cpu_state, _, trace| {
+        push_trace(&mut trace_text, cpu_state, trace)
+    });
+
+    trace_text
+        .lines()
+        .filter(|line| line.starts_with("; Clocks:"))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn push_and_pop_go_through_ss_sp() {
+    let mut cpu_state = CpuState::new();
+    cpu_state.set_new_register_value(Reg::Ss, 0x1000);
+
+    let cpu_state = run_source_with(
+        cpu_state,
+        "mov sp, 0x100\nmov ax, 0x1234\npush ax\npush sp\npop bx\npop word [2]\n",
+    );
+
+    assert_eq!(cpu_state.memory.read_u16(0x100FE), 0x1234);
+    // The 8086 pushes SP after decrementing it
+    assert_eq!(cpu_state.get_register_value(Reg::Bx), 0xFC);
+    assert_eq!(cpu_state.memory.read_u16(2), 0x1234);
+    assert_eq!(cpu_state.get_register_value(Reg::Sp), 0x100);
+}
+
+#[test]
+fn calls_return_past_their_arguments() {
+    let cpu_state = run_source(
+        "mov sp, 0x100\n\
+         push ax\n\
+         call near_sub\n\
+         mov word [0x200], 0x20\n\
+         mov word [0x202], 0x1000\n\
+         push ax\n\
+         push ax\n\
+         call far [0x200]\n\
+         jmp done\n\
+         near_sub:\n\
+         mov cx, sp\n\
+         ret 2\n\
+         far_sub:\n\
+         mov dx, sp\n\
+         retf 4\n\
+         done:\n",
+    );
+
+    // The near call pushes IP on top of the argument, the far call CS and IP
+    assert_eq!(cpu_state.get_register_value(Reg::Cx), 0xFC);
+    assert_eq!(cpu_state.get_register_value(Reg::Dx), 0xF8);
+    assert_eq!(cpu_state.get_register_value(Reg::Sp), 0x100);
+    assert_eq!(cpu_state.get_ip() as usize, 0x25);
+}
+
+#[test]
+fn far_calls_fetch_from_the_new_cs() {
+    // 1001:0010 is the same byte as 1000:0020, while IP 0x10 in the old segment would run the
+    // decoy `mov cx, 1`
+    let source = format!(
+        "call 0x{:x}:0x10\njmp done\n{}mov cx, 1\nhlt\n{}mov bx, cs\nretf\ndone:\n",
+        LOAD_SEGMENT + 1,
+        "nop\n".repeat(9),
+        "nop\n".repeat(12),
+    );
+    let mut cpu_state = CpuState::new();
+    cpu_state.set_new_register_value(Reg::Sp, 0x100);
+    let cpu_state = run_source_with(cpu_state, &source);
+
+    assert_eq!(cpu_state.get_register_value(Reg::Bx), LOAD_SEGMENT + 1);
+    assert_eq!(cpu_state.get_register_value(Reg::Cx), 0);
+    assert_eq!(cpu_state.get_register_value(Reg::Cs), LOAD_SEGMENT);
+    assert_eq!(cpu_state.get_ip(), 0x23);
+}
+
+#[test]
+fn every_jmp_form_moves_ip() {
+    // Each jump skips a hlt. The last one goes to 1001:0018, which is the same byte as 1000:0028
+    let cpu_state = run_source(
+        "mov ax, 6\njmp ax\nhlt\n\
+         mov word [0x200], 17\njmp [0x200]\nhlt\n\
+         mov word [0x204], 34\nmov word [0x206], 0x1000\njmp far [0x204]\nhlt\n\
+         jmp 0x1001:0x18\nhlt\n\
+         mov bx, cs\n",
+    );
+
+    assert_eq!(cpu_state.get_register_value(Reg::Bx), LOAD_SEGMENT + 1);
+    assert_eq!(cpu_state.get_ip(), 0x1A);
+}
+
+#[test]
+fn exchanges_loads_pointers_and_extends_signs() {
+    let mut cpu_state = CpuState::new();
+    cpu_state.memory.write_u16(0x300, 0x1234);
+    cpu_state.memory.write_u16(0x302, 0x5678);
+    cpu_state.memory.write_u8(0x405, 0x99);
+
+    let cpu_state = run_source_with(
+        cpu_state,
+        "mov ax, 1\nmov bp, 2\nxchg ax, bp\nxchg cx, [0x300]\n\
+         mov bx, 0x400\nmov al, 5\nxlat\ncbw\ncwd\n\
+         lds si, [0x300]\nmov word [0x302], 0x9ABC\nles di, [0x300]\n",
+    );
+
+    assert_eq!(cpu_state.get_register_value(Reg::Bp), 1);
+    assert_eq!(cpu_state.get_register_value(Reg::Cx), 0x1234);
+    assert_eq!(cpu_state.get_register_value(Reg::Ax), 0xFF99);
+    assert_eq!(cpu_state.get_register_value(Reg::Dx), 0xFFFF);
+    assert_eq!(cpu_state.get_register_value(Reg::Si), 0);
+    assert_eq!(cpu_state.get_register_value(Reg::Ds), 0x5678);
+    // DS moved, so the second pointer comes from 5678:0300
+    assert_eq!(cpu_state.get_register_value(Reg::Di), 0);
+    assert_eq!(cpu_state.get_register_value(Reg::Es), 0x9ABC);
+}
+
+#[test]
+fn decimal_adjustments_fix_up_bcd() {
+    for (source, ax, carry) in [
+        ("mov al, 0x79\nadd al, 0x35\ndaa", 0x14, true),
+        ("mov al, 0x35\nsub al, 0x47\ndas", 0x88, true),
+        ("mov ax, 9\nadd al, 8\naaa", 0x0107, true),
+        ("mov ax, 0x0102\nsub al, 5\naas", 0x0007, true),
+        ("mov al, 63\naam", 0x0603, false),
+        ("mov ax, 0x0407\naad", 0x002F, false),
+    ] {
+        let cpu_state = run_source(source);
+
+        assert_eq!(cpu_state.get_register_value(Reg::Ax), ax, "{}", source);
+        assert_eq!(cpu_state.get_flag(Flag::Carry), carry, "{}", source);
+    }
+}
+
+#[test]
+fn port_io_stops_the_simulation() {
+    let program = assemble("mov dx, 0x60\nin al, dx\nmov bx, 1\n").unwrap();
+    let mut cpu_state = CpuState::new();
+    load(&mut cpu_state, &program);
+
+    let stop = run(&mut cpu_state, &program, 1000, |_, _, _| {});
+
+    assert_eq!(stop, StopReason::Unsupported(Op::In));
+    assert_eq!(cpu_state.get_ip(), 3);
+    assert_eq!(cpu_state.get_register_value(Reg::Bx), 0);
+}
+
+#[test]
+fn pushf_sets_the_reserved_bits() {
+    let cpu_state = run_source("stc\npushf\npop ax\nmov bx, 0xFFFF\npush bx\npopf\n");
+
+    assert_eq!(cpu_state.get_register_value(Reg::Ax), 0xF003);
+    assert_eq!(cpu_state.flags, 0x0FD5);
+}
+
+#[test]
+fn multiplies_into_ax_and_dx() {
+    let cpu_state = run_source(
+        "mov al, 200\nmov bl, 3\nmul bl\nmov cx, ax\nmov ax, -300\nmov bx, 1000\nimul bx\n",
+    );
+
+    assert_eq!(cpu_state.get_register_value(Reg::Cx), 600);
+    assert_eq!(cpu_state.get_register_value(Reg::Ax), 0x6C20);
+    assert_eq!(cpu_state.get_register_value(Reg::Dx), 0xFFFB);
+    assert!(cpu_state.get_flag(Flag::Carry) && cpu_state.get_flag(Flag::Overflow));
+
+    // A product that fits in the low half clears CF and OF
+    let cpu_state = run_source("stc\nmov al, -10\nmov bl, 3\nimul bl\n");
+    assert_eq!(cpu_state.get_register_value(Reg::Ax), (-30_i16) as u16);
+    assert!(!cpu_state.get_flag(Flag::Carry) && !cpu_state.get_flag(Flag::Overflow));
+}
+
+#[test]
+fn signed_division_rounds_toward_zero() {
+    let cpu_state = run_source(
+        "mov ax, -100\nmov bl, 7\nidiv bl\nmov cx, ax\nmov dx, 1\nmov ax, 5\nmov bx, 3\ndiv bx\n",
+    );
+
+    // Quotient -14 in AL, remainder -2 in AH
+    assert_eq!(cpu_state.get_register_value(Reg::Cx), 0xFEF2);
+    assert_eq!(cpu_state.get_register_value(Reg::Ax), 0x5557);
+    assert_eq!(cpu_state.get_register_value(Reg::Dx), 0);
+}
+
+#[test]
+fn divide_errors_go_through_interrupt_0() {
+    for division in [
+        "mov bl, 0\ndiv bl",
+        "mov ax, 0x7F00\nmov bl, 1\ndiv bl",
+        "mov ax, 0x8000\nmov dx, -1\nmov bx, -1\nidiv bx",
+    ] {
+        // The handler at 0040:0100 sits outside of the program, so running stops there
+        let mut cpu_state = CpuState::new();
+        cpu_state.memory.write_u16(0, 0x100);
+        cpu_state.memory.write_u16(2, 0x40);
+        cpu_state.set_new_register_value(Reg::Sp, 0x100);
+        cpu_state.set_flag(Flag::Interrupt, true);
+
+        let source = format!("{}\nmov cx, 1\n", division);
+        let cpu_state = run_source_with(cpu_state, &source);
+
+        assert_eq!(cpu_state.get_ip(), 0x100, "{}", division);
+        assert_eq!(cpu_state.get_register_value(Reg::Cs), 0x40);
+        assert_eq!(cpu_state.get_register_value(Reg::Cx), 0);
+        assert!(!cpu_state.get_flag(Flag::Interrupt));
+
+        // FLAGS, CS and the IP of the next instruction are on the stack
+        let next_ip = assemble(division).unwrap().len() as u16;
+        assert_eq!(cpu_state.memory.read_u16(0xFA), next_ip);
+        assert_eq!(cpu_state.memory.read_u16(0xFC), LOAD_SEGMENT);
+        assert_eq!(cpu_state.memory.read_u16(0xFE) & 0x0200, 0x0200);
+    }
+}
+
+#[test]
+fn arithmetic_flags_at_the_boundaries() {
+    for (source, al, flags) in [
+        ("mov al, 0x7F\nadd al, 1", 0x80, "ASO"),
+        ("mov al, 0x80\nsub al, 1", 0x7F, "AO"),
+        // Words overflow at bit 15, but PF still only looks at the low byte
+        ("mov ax, 0x7FFF\nadd ax, 1", 0x00, "PASO"),
+        ("stc\nmov al, 0xFF\nadc al, 0", 0x00, "CPAZ"),
+        ("stc\nmov al, 0\nsbb al, 0", 0xFF, "CPAS"),
+        ("mov al, 0\nneg al", 0x00, "PZ"),
+        ("mov al, 1\nneg al", 0xFF, "CPAS"),
+        ("mov al, 0x80\nneg al", 0x80, "CSO"),
+        // inc and dec leave CF the way it was
+        ("stc\nmov al, 0xFF\ninc al", 0x00, "CPAZ"),
+        ("mov al, 0\ndec al", 0xFF, "PAS"),
+        // Logic operations clear CF, OF and AF
+        ("mov al, 0x7F\nadd al, 1\nstc\nand al, 0xFF", 0x80, "S"),
+    ] {
+        let cpu_state = run_source(source);
+
+        assert_eq!(cpu_state.get_register_value(Reg::Al), al, "{}", source);
+        assert_eq!(format_flags(cpu_state.flags), flags, "{}", source);
+    }
+}
+
+#[test]
+fn shifts_set_overflow_from_the_last_step() {
+    // shl: OF is set when the sign changes, shr: OF is the original sign, sar: OF is always clear
+    let cases = [
+        ("mov bx, 0x4000\nshl bx, 1", 0x8000, false, true),
+        ("mov bx, 0x8000\nshr bx, 1", 0x4000, false, true),
+        ("mov bx, 0x8001\nsar bx, 1", 0xC000, true, false),
+        ("mov bx, 0x8001\nrol bx, 1", 0x0003, true, true),
+        ("stc\nmov bx, 0x0001\nrcr bx, 1", 0x8000, true, true),
+    ];
+
+    for (source, result, carry, overflow) in cases {
+        let cpu_state = run_source(source);
+
+        assert_eq!(cpu_state.get_register_value(Reg::Bx), result, "{}", source);
+        assert_eq!(cpu_state.get_flag(Flag::Carry), carry, "{}", source);
+        assert_eq!(cpu_state.get_flag(Flag::Overflow), overflow, "{}", source);
+    }
+}
+
+#[test]
+fn shift_counts_are_not_masked() {
+    // Later CPUs would mask the count to 1 and shift once. The 8086 shifts 33 times
+    let cpu_state = run_source(
+        "mov ax, 0xFFFF\nmov cl, 33\nshl ax, cl\nmov bx, 0x8001\nmov cl, 17\nrcl bx, cl\n",
+    );
+
+    assert_eq!(cpu_state.get_register_value(Reg::Ax), 0);
+    // Rotating through 17 bits 17 times comes back around
+    assert_eq!(cpu_state.get_register_value(Reg::Bx), 0x8001);
+
+    // A count of 0 leaves the flags alone
+    let cpu_state = run_source("stc\nmov dx, 0x4000\nmov cl, 0\nshl dx, cl\n");
+    assert!(cpu_state.get_flag(Flag::Carry));
+}
+
+#[test]
+fn clocks_follow_the_base_and_ea_tables() {
+    let lines = clock_lines(
+        CpuState::new(),
+        "mov bx, 1000\nmov cx, bx\nmov dx, [1000]\nmov cx, [bp]\nmov [si + 1000], cx\n\
+         add word [di + 1000], 50\nmov ax, [1000]\n",
+    );
+
+    // [bp] always carries a displacement, and the accumulator form of mov has no EA to compute
+    assert_eq!(
+        lines,
+        [
+            "; Clocks: +4 = 4",
+            "; Clocks: +2 = 6",
+            "; Clocks: +14 = 20 (8 + 6ea)",
+            "; Clocks: +17 = 37 (8 + 9ea)",
+            "; Clocks: +18 = 55 (9 + 9ea)",
+            "; Clocks: +26 = 81 (17 + 9ea)",
+            "; Clocks: +10 = 91",
+        ]
+    );
+}
+
+#[test]
+fn segment_overrides_add_2_clocks_to_the_ea() {
+    let lines = clock_lines(
+        CpuState::new(),
+        "mov dx, [es:1000]\nmov [cs:bp], cx\nadd word [ss:bx + si + 8], 50\nmov ax, [ds:1000]\n\
+         es inc ax\n",
+    );
+
+    // The prefix on an instruction without a memory operand does nothing for the clocks
+    assert_eq!(
+        lines,
+        [
+            "; Clocks: +16 = 16 (8 + 8ea)",
+            "; Clocks: +20 = 36 (9 + 11ea)",
+            "; Clocks: +30 = 66 (17 + 13ea)",
+            "; Clocks: +12 = 78 (10 + 2ea)",
+            "; Clocks: +2 = 80",
+        ]
+    );
+}
+
+#[test]
+fn split_word_transfers_cost_4_clocks() {
+    // The 8086 only splits words at odd addresses
+    let lines = clock_lines(CpuState::new(), "mov [bx + 1], ax\nmov [bx + 2], ax\n");
+    assert_eq!(
+        lines,
+        [
+            "; Clocks: +22 = 22 (9 + 9ea + 4p)",
+            "; Clocks: +18 = 40 (9 + 9ea)"
+        ]
+    );
+
+    // The 8088 splits every word, bytes never are
+    let mut cpu_state = CpuState::new();
+    cpu_state.cpu_model = CpuModel::I8088;
+    let lines = clock_lines(cpu_state, "mov ax, [1000]\nmov [bx], ax\nmov [bx], al\n");
+    assert_eq!(
+        lines,
+        [
+            "; Clocks: +14 = 14 (10 + 4p)",
+            "; Clocks: +18 = 32 (9 + 5ea + 4p)",
+            "; Clocks: +14 = 46 (9 + 5ea)",
+        ]
+    );
+}
+
+#[test]
+fn shifts_by_cl_take_4_clocks_per_bit() {
+    for count in [0, 1, 5, 33] {
+        let mut cpu_state = CpuState::new();
+        cpu_state.total_clocks = Some(0);
+
+        let source = format!("mov cl, {}\nshl ax, cl\nror word [bx], cl\n", count);
+        let cpu_state = run_source_with(cpu_state, &source);
+
+        // mov cl, imm takes 4, then 8 + 4n for the register and 20 + 4n + 5ea for memory
+        assert_eq!(cpu_state.total_clocks, Some(4 + 8 + 20 + 5 + 8 * count));
+    }
+}
+
+#[test]
+fn rep_movs_copies_in_either_direction() {
+    let mut cpu_state = CpuState::new();
+    cpu_state.set_new_register_value(Reg::Es, 0x100);
+    cpu_state.memory.load(0x10, b"abcd");
+
+    let cpu_state = run_source_with(
+        cpu_state,
+        "mov si, 0x10\nmov di, 0\nmov cx, 2\nrep movsw\n\
+         std\nmov si, 0x13\nmov di, 0x13\nmov cx, 4\nrep movsb\n",
+    );
+
+    assert_eq!(&cpu_state.memory.as_bytes()[0x1000..0x1004], b"abcd");
+    assert_eq!(&cpu_state.memory.as_bytes()[0x1010..0x1014], b"abcd");
+    assert_eq!(cpu_state.get_register_value(Reg::Si), 0x0F);
+    assert_eq!(cpu_state.get_register_value(Reg::Di), 0x0F);
+    assert_eq!(cpu_state.get_register_value(Reg::Cx), 0);
+}
+
+#[test]
+fn repne_scas_stops_at_the_first_match() {
+    let mut cpu_state = CpuState::new();
+    cpu_state.memory.load(0x100, b"hello");
+
+    // 0x6C is 'l'
+    let cpu_state = run_source_with(
+        cpu_state,
+        "mov di, 0x100\nmov cx, 5\nmov al, 0x6C\nrepne scasb\n",
+    );
+
+    // DI is left one past the match and CX counts what's left of the string
+    assert_eq!(cpu_state.get_register_value(Reg::Di), 0x103);
+    assert_eq!(cpu_state.get_register_value(Reg::Cx), 2);
+    assert!(cpu_state.get_flag(Flag::Zero));
+}
+
+#[test]
+fn rep_pays_setup_once_and_every_iteration() {
+    let mut cpu_state = CpuState::new();
+    cpu_state.total_clocks = Some(0);
+
+    let cpu_state = run_source_with(cpu_state, "mov cx, 3\nrep stosb\nrep stosb\n");
+
+    // mov cx, imm takes 4, then 9 + 10n, with n = 0 the second time around
+    assert_eq!(cpu_state.total_clocks, Some(4 + 9 + 30 + 9));
+}
+
+#[test]
+fn int_vectors_through_the_table_and_iret_returns() {
+    // The handler for int 0x21 is right after hlt, at offset 0x15 of the program. The vector
+    // reaches it through a segment of its own
+    let source = format!(
+        "mov sp, 0x100\n\
+         mov word [0x84], 0x25\n\
+         mov word [0x86], 0x{:x}\n\
+         stc\n\
+         int 0x21\n\
+         mov bx, ax\n\
+         hlt\n\
+         mov ax, 7\n\
+         mov dx, cs\n\
+         clc\n\
+         into\n\
+         iret\n",
+        LOAD_SEGMENT - 1
+    );
+    let cpu_state = run_source(&source);
+
+    assert_eq!(cpu_state.get_register_value(Reg::Bx), 7);
+    assert_eq!(cpu_state.get_register_value(Reg::Dx), LOAD_SEGMENT - 1);
+    assert_eq!(cpu_state.get_register_value(Reg::Cs), LOAD_SEGMENT);
+    assert_eq!(cpu_state.get_register_value(Reg::Sp), 0x100);
+    // FLAGS come back the way they were pushed
+    assert!(cpu_state.get_flag(Flag::Carry));
+    assert_eq!(cpu_state.get_ip(), 0x15);
+}
+
+#[test]
+fn registered_int_handlers_run_natively() {
+    let mut cpu_state = CpuState::new();
+    cpu_state.set_new_register_value(Reg::Ds, 0x100);
+    cpu_state.register_int_handler(0x21, |cpu_state, memory| {
+        let address = segmented_address(
+            cpu_state.get_register_value(Reg::Ds),
+            cpu_state.get_register_value(Reg::Dx),
+        );
+        memory.write_u8(address, cpu_state.get_register_value(Reg::Al) as u8);
+        cpu_state.set_new_register_value(Reg::Ax, 0);
+    });
+
+    let cpu_state = run_source_with(
+        cpu_state,
+        "mov sp, 0x100\nmov dx, 0x10\nmov al, 0x41\nint 0x21\nmov bx, 1\n",
+    );
+
+    assert_eq!(cpu_state.memory.read_u8(0x1010), 0x41);
+    assert_eq!(cpu_state.get_register_value(Reg::Ax), 0);
+    // Nothing was pushed and the program went on after the int
+    assert_eq!(cpu_state.get_register_value(Reg::Sp), 0x100);
+    assert_eq!(cpu_state.get_register_value(Reg::Bx), 1);
+}